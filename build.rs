@@ -1,166 +1,376 @@
+use std::env;
 use std::path::PathBuf;
 
 // https://doc.rust-lang.org/cargo/reference/build-scripts.html
 fn main() {
     println!("cargo:rerun-if-changed=build.rs");
 
+    // bash
+    if enabled("bash") {
+        let bash_dir: PathBuf = ["vendor", "tree-sitter-bash", "src"].iter().collect();
+
+        println!("cargo:rerun-if-changed=vendor/tree-sitter-bash/src/parser.c");
+        cc::Build::new()
+            .include(&bash_dir)
+            .warnings(false)
+            .file(bash_dir.join("parser.c"))
+            .compile("tree-sitter-bash");
+
+        println!("cargo:rerun-if-changed=vendor/tree-sitter-bash/src/scanner.cc");
+        cc::Build::new()
+            .include(&bash_dir)
+            .cpp(true)
+            .warnings(false)
+            .file(bash_dir.join("scanner.cc"))
+            .compile("tree_sitter_bash_scanner");
+    }
+
+    // c
+    if enabled("c") {
+        let c_dir: PathBuf = ["vendor", "tree-sitter-c", "src"].iter().collect();
+
+        println!("cargo:rerun-if-changed=vendor/tree-sitter-c/src/parser.c");
+        cc::Build::new()
+            .include(&c_dir)
+            .warnings(false)
+            .file(c_dir.join("parser.c"))
+            .compile("tree-sitter-c");
+    }
+
+    // c#
+    if enabled("csharp") {
+        let c_sharp_dir: PathBuf = ["vendor", "tree-sitter-c-sharp", "src"].iter().collect();
+
+        println!("cargo:rerun-if-changed=vendor/tree-sitter-c-sharp/src/parser.c");
+        cc::Build::new()
+            .include(&c_sharp_dir)
+            .warnings(false)
+            .file(c_sharp_dir.join("parser.c"))
+            .compile("tree-sitter-c-sharp");
+
+        println!("cargo:rerun-if-changed=vendor/tree-sitter-c-sharp/src/scanner.c");
+        cc::Build::new()
+            .include(&c_sharp_dir)
+            .warnings(false)
+            .file(c_sharp_dir.join("scanner.c"))
+            .compile("tree_sitter_c_sharp_scanner");
+    }
+
     // cpp
-    let cpp_dir: PathBuf = ["vendor", "tree-sitter-cpp", "src"].iter().collect();
-
-    println!("cargo:rerun-if-changed=vendor/tree-sitter-cpp/src/parser.c");
-    cc::Build::new()
-        .include(&cpp_dir)
-        .warnings(false)
-        .file(cpp_dir.join("parser.c"))
-        .compile("tree-sitter-cpp");
-
-    println!("cargo:rerun-if-changed=vendor/tree-sitter-cpp/src/scanner.cc");
-    cc::Build::new()
-        .include(&cpp_dir)
-        .cpp(true)
-        .warnings(false)
-        .file(cpp_dir.join("scanner.cc"))
-        .compile("tree_sitter_cpp_scanner");
+    if enabled("cpp") {
+        let cpp_dir: PathBuf = ["vendor", "tree-sitter-cpp", "src"].iter().collect();
+
+        println!("cargo:rerun-if-changed=vendor/tree-sitter-cpp/src/parser.c");
+        cc::Build::new()
+            .include(&cpp_dir)
+            .warnings(false)
+            .file(cpp_dir.join("parser.c"))
+            .compile("tree-sitter-cpp");
+
+        println!("cargo:rerun-if-changed=vendor/tree-sitter-cpp/src/scanner.cc");
+        cc::Build::new()
+            .include(&cpp_dir)
+            .cpp(true)
+            .warnings(false)
+            .file(cpp_dir.join("scanner.cc"))
+            .compile("tree_sitter_cpp_scanner");
+    }
+
+    // css
+    if enabled("css") {
+        let css_dir: PathBuf = ["vendor", "tree-sitter-css", "src"].iter().collect();
+
+        println!("cargo:rerun-if-changed=vendor/tree-sitter-css/src/parser.c");
+        cc::Build::new()
+            .include(&css_dir)
+            .warnings(false)
+            .file(css_dir.join("parser.c"))
+            .compile("tree-sitter-css");
+
+        println!("cargo:rerun-if-changed=vendor/tree-sitter-css/src/scanner.c");
+        cc::Build::new()
+            .include(&css_dir)
+            .warnings(false)
+            .file(css_dir.join("scanner.c"))
+            .compile("tree_sitter_css_scanner");
+    }
 
     // elixir
-    let elixir_dir: PathBuf = ["vendor", "tree-sitter-elixir", "src"].iter().collect();
-
-    println!("cargo:rerun-if-changed=vendor/tree-sitter-elixir/src/parser.c");
-    cc::Build::new()
-        .include(&elixir_dir)
-        .warnings(false)
-        .file(elixir_dir.join("parser.c"))
-        .compile("tree-sitter-elixir");
-
-    println!("cargo:rerun-if-changed=vendor/tree-sitter-elixir/src/scanner.cc");
-    cc::Build::new()
-        .include(&elixir_dir)
-        .warnings(false)
-        .cpp(true)
-        .file(elixir_dir.join("scanner.cc"))
-        .compile("tree_sitter_elixir_scanner");
+    if enabled("elixir") {
+        let elixir_dir: PathBuf = ["vendor", "tree-sitter-elixir", "src"].iter().collect();
+
+        println!("cargo:rerun-if-changed=vendor/tree-sitter-elixir/src/parser.c");
+        cc::Build::new()
+            .include(&elixir_dir)
+            .warnings(false)
+            .file(elixir_dir.join("parser.c"))
+            .compile("tree-sitter-elixir");
+
+        println!("cargo:rerun-if-changed=vendor/tree-sitter-elixir/src/scanner.cc");
+        cc::Build::new()
+            .include(&elixir_dir)
+            .warnings(false)
+            .cpp(true)
+            .file(elixir_dir.join("scanner.cc"))
+            .compile("tree_sitter_elixir_scanner");
+    }
 
     // elm
-    let elm_dir: PathBuf = ["vendor", "tree-sitter-elm", "src"].iter().collect();
-
-    println!("cargo:rerun-if-changed=vendor/tree-sitter-elm/src/parser.c");
-    cc::Build::new()
-        .include(&elm_dir)
-        .warnings(false)
-        .file(elm_dir.join("parser.c"))
-        .compile("tree-sitter-elm");
-
-    println!("cargo:rerun-if-changed=vendor/tree-sitter-elm/src/scanner.cc");
-    cc::Build::new()
-        .include(&elm_dir)
-        .cpp(true)
-        .warnings(false)
-        .file(elm_dir.join("scanner.cc"))
-        .compile("tree_sitter_elm_scanner");
+    if enabled("elm") {
+        let elm_dir: PathBuf = ["vendor", "tree-sitter-elm", "src"].iter().collect();
+
+        println!("cargo:rerun-if-changed=vendor/tree-sitter-elm/src/parser.c");
+        cc::Build::new()
+            .include(&elm_dir)
+            .warnings(false)
+            .file(elm_dir.join("parser.c"))
+            .compile("tree-sitter-elm");
+
+        println!("cargo:rerun-if-changed=vendor/tree-sitter-elm/src/scanner.cc");
+        cc::Build::new()
+            .include(&elm_dir)
+            .cpp(true)
+            .warnings(false)
+            .file(elm_dir.join("scanner.cc"))
+            .compile("tree_sitter_elm_scanner");
+    }
+
+    // go
+    if enabled("go") {
+        let go_dir: PathBuf = ["vendor", "tree-sitter-go", "src"].iter().collect();
+
+        println!("cargo:rerun-if-changed=vendor/tree-sitter-go/src/parser.c");
+        cc::Build::new()
+            .include(&go_dir)
+            .warnings(false)
+            .file(go_dir.join("parser.c"))
+            .compile("tree-sitter-go");
+    }
 
     // haskell
-    let haskell_dir: PathBuf = ["vendor", "tree-sitter-haskell", "src"].iter().collect();
-
-    println!("cargo:rerun-if-changed=vendor/tree-sitter-haskell/src/parser.c");
-    cc::Build::new()
-        .include(&haskell_dir)
-        .warnings(false)
-        .file(haskell_dir.join("parser.c"))
-        .compile("tree-sitter-haskell");
-
-    println!("cargo:rerun-if-changed=vendor/tree-sitter-haskell/src/scanner.cc");
-    cc::Build::new()
-        .include(&haskell_dir)
-        .warnings(false)
-        .file(haskell_dir.join("scanner.c"))
-        .compile("tree_sitter_haskell_scanner");
+    if enabled("haskell") {
+        let haskell_dir: PathBuf = ["vendor", "tree-sitter-haskell", "src"].iter().collect();
+
+        println!("cargo:rerun-if-changed=vendor/tree-sitter-haskell/src/parser.c");
+        cc::Build::new()
+            .include(&haskell_dir)
+            .warnings(false)
+            .file(haskell_dir.join("parser.c"))
+            .compile("tree-sitter-haskell");
+
+        println!("cargo:rerun-if-changed=vendor/tree-sitter-haskell/src/scanner.cc");
+        cc::Build::new()
+            .include(&haskell_dir)
+            .warnings(false)
+            .file(haskell_dir.join("scanner.c"))
+            .compile("tree_sitter_haskell_scanner");
+    }
+
+    // html
+    if enabled("html") {
+        let html_dir: PathBuf = ["vendor", "tree-sitter-html", "src"].iter().collect();
+
+        println!("cargo:rerun-if-changed=vendor/tree-sitter-html/src/parser.c");
+        cc::Build::new()
+            .include(&html_dir)
+            .warnings(false)
+            .file(html_dir.join("parser.c"))
+            .compile("tree-sitter-html");
+
+        println!("cargo:rerun-if-changed=vendor/tree-sitter-html/src/scanner.cc");
+        cc::Build::new()
+            .include(&html_dir)
+            .cpp(true)
+            .warnings(false)
+            .file(html_dir.join("scanner.cc"))
+            .compile("tree_sitter_html_scanner");
+    }
+
+    // java
+    if enabled("java") {
+        let java_dir: PathBuf = ["vendor", "tree-sitter-java", "src"].iter().collect();
+
+        println!("cargo:rerun-if-changed=vendor/tree-sitter-java/src/parser.c");
+        cc::Build::new()
+            .include(&java_dir)
+            .warnings(false)
+            .file(java_dir.join("parser.c"))
+            .compile("tree-sitter-java");
+    }
 
     // javascript
-    let javascript_dir: PathBuf = ["vendor", "tree-sitter-javascript", "src"].iter().collect();
-
-    println!("cargo:rerun-if-changed=vendor/tree-sitter-javascript/src/parser.c");
-    cc::Build::new()
-        .include(&javascript_dir)
-        .warnings(false)
-        .file(javascript_dir.join("parser.c"))
-        .compile("tree-sitter-javascript");
-
-    println!("cargo:rerun-if-changed=vendor/tree-sitter-javascript/src/scanner.c");
-    cc::Build::new()
-        .include(&javascript_dir)
-        .warnings(false)
-        .file(javascript_dir.join("scanner.c"))
-        .compile("tree_sitter_javascript_scanner");
+    if enabled("javascript") {
+        let javascript_dir: PathBuf = ["vendor", "tree-sitter-javascript", "src"].iter().collect();
+
+        println!("cargo:rerun-if-changed=vendor/tree-sitter-javascript/src/parser.c");
+        cc::Build::new()
+            .include(&javascript_dir)
+            .warnings(false)
+            .file(javascript_dir.join("parser.c"))
+            .compile("tree-sitter-javascript");
+
+        println!("cargo:rerun-if-changed=vendor/tree-sitter-javascript/src/scanner.c");
+        cc::Build::new()
+            .include(&javascript_dir)
+            .warnings(false)
+            .file(javascript_dir.join("scanner.c"))
+            .compile("tree_sitter_javascript_scanner");
+    }
+
+    // json
+    if enabled("json") {
+        let json_dir: PathBuf = ["vendor", "tree-sitter-json", "src"].iter().collect();
+
+        println!("cargo:rerun-if-changed=vendor/tree-sitter-json/src/parser.c");
+        cc::Build::new()
+            .include(&json_dir)
+            .warnings(false)
+            .file(json_dir.join("parser.c"))
+            .compile("tree-sitter-json");
+    }
 
     // php
-    let php_dir: PathBuf = ["vendor", "tree-sitter-php", "src"].iter().collect();
-
-    println!("cargo:rerun-if-changed=vendor/tree-sitter-php/src/parser.c");
-    cc::Build::new()
-        .include(&php_dir)
-        .warnings(false)
-        .file(php_dir.join("parser.c"))
-        .compile("tree-sitter-php");
-
-    println!("cargo:rerun-if-changed=vendor/tree-sitter-php/src/scanner.cc");
-    cc::Build::new()
-        .include(&php_dir)
-        .cpp(true)
-        .warnings(false)
-        .file(php_dir.join("scanner.cc"))
-        .compile("tree_sitter_php_scanner");
+    if enabled("php") {
+        let php_dir: PathBuf = ["vendor", "tree-sitter-php", "src"].iter().collect();
+
+        println!("cargo:rerun-if-changed=vendor/tree-sitter-php/src/parser.c");
+        cc::Build::new()
+            .include(&php_dir)
+            .warnings(false)
+            .file(php_dir.join("parser.c"))
+            .compile("tree-sitter-php");
+
+        println!("cargo:rerun-if-changed=vendor/tree-sitter-php/src/scanner.cc");
+        cc::Build::new()
+            .include(&php_dir)
+            .cpp(true)
+            .warnings(false)
+            .file(php_dir.join("scanner.cc"))
+            .compile("tree_sitter_php_scanner");
+    }
+
+    // python
+    if enabled("python") {
+        let python_dir: PathBuf = ["vendor", "tree-sitter-python", "src"].iter().collect();
+
+        println!("cargo:rerun-if-changed=vendor/tree-sitter-python/src/parser.c");
+        cc::Build::new()
+            .include(&python_dir)
+            .warnings(false)
+            .file(python_dir.join("parser.c"))
+            .compile("tree-sitter-python");
+
+        println!("cargo:rerun-if-changed=vendor/tree-sitter-python/src/scanner.c");
+        cc::Build::new()
+            .include(&python_dir)
+            .warnings(false)
+            .file(python_dir.join("scanner.c"))
+            .compile("tree_sitter_python_scanner");
+    }
 
     // ruby
-    let ruby_dir: PathBuf = ["vendor", "tree-sitter-ruby", "src"].iter().collect();
-
-    println!("cargo:rerun-if-changed=vendor/tree-sitter-ruby/src/parser.c");
-    cc::Build::new()
-        .include(&ruby_dir)
-        .warnings(false)
-        .file(ruby_dir.join("parser.c"))
-        .compile("tree-sitter-ruby");
-
-    println!("cargo:rerun-if-changed=vendor/tree-sitter-ruby/src/scanner.cc");
-    cc::Build::new()
-        .include(&ruby_dir)
-        .cpp(true)
-        .warnings(false)
-        .file(ruby_dir.join("scanner.cc"))
-        .compile("tree_sitter_ruby_scanner");
+    if enabled("ruby") {
+        let ruby_dir: PathBuf = ["vendor", "tree-sitter-ruby", "src"].iter().collect();
+
+        println!("cargo:rerun-if-changed=vendor/tree-sitter-ruby/src/parser.c");
+        cc::Build::new()
+            .include(&ruby_dir)
+            .warnings(false)
+            .file(ruby_dir.join("parser.c"))
+            .compile("tree-sitter-ruby");
+
+        println!("cargo:rerun-if-changed=vendor/tree-sitter-ruby/src/scanner.cc");
+        cc::Build::new()
+            .include(&ruby_dir)
+            .cpp(true)
+            .warnings(false)
+            .file(ruby_dir.join("scanner.cc"))
+            .compile("tree_sitter_ruby_scanner");
+    }
 
     // rust
-    let rust_dir: PathBuf = ["vendor", "tree-sitter-rust", "src"].iter().collect();
-
-    println!("cargo:rerun-if-changed=vendor/tree-sitter-rust/src/parser.c");
-    cc::Build::new()
-        .include(&rust_dir)
-        .warnings(false)
-        .file(rust_dir.join("parser.c"))
-        .compile("tree-sitter-rust");
-
-    println!("cargo:rerun-if-changed=vendor/tree-sitter-rust/src/scanner.c");
-    cc::Build::new()
-        .include(&rust_dir)
-        .warnings(false)
-        .file(rust_dir.join("scanner.c"))
-        .compile("tree_sitter_rust_scanner");
+    if enabled("rust") {
+        let rust_dir: PathBuf = ["vendor", "tree-sitter-rust", "src"].iter().collect();
+
+        println!("cargo:rerun-if-changed=vendor/tree-sitter-rust/src/parser.c");
+        cc::Build::new()
+            .include(&rust_dir)
+            .warnings(false)
+            .file(rust_dir.join("parser.c"))
+            .compile("tree-sitter-rust");
+
+        println!("cargo:rerun-if-changed=vendor/tree-sitter-rust/src/scanner.c");
+        cc::Build::new()
+            .include(&rust_dir)
+            .warnings(false)
+            .file(rust_dir.join("scanner.c"))
+            .compile("tree_sitter_rust_scanner");
+    }
+
+    // tsx
+    if enabled("tsx") {
+        let tsx_dir: PathBuf = ["vendor", "tree-sitter-typescript", "tsx", "src"]
+            .iter()
+            .collect();
+
+        println!("cargo:rerun-if-changed=vendor/tree-sitter-typescript/tsx/src/parser.c");
+        cc::Build::new()
+            .include(&tsx_dir)
+            .warnings(false)
+            .file(tsx_dir.join("parser.c"))
+            .compile("tree-sitter-tsx");
+
+        println!("cargo:rerun-if-changed=vendor/tree-sitter-typescript/tsx/src/scanner.c");
+        cc::Build::new()
+            .include(&tsx_dir)
+            .warnings(false)
+            .file(tsx_dir.join("scanner.c"))
+            .compile("tree_sitter_tsx_scanner");
+    }
 
     // typescript
-    let typescript_dir: PathBuf = ["vendor", "tree-sitter-typescript", "typescript", "src"]
-        .iter()
-        .collect();
-
-    println!("cargo:rerun-if-changed=vendor/tree-sitter-typescript/typescript/src/parser.c");
-    cc::Build::new()
-        .include(&typescript_dir)
-        .warnings(false)
-        .file(typescript_dir.join("parser.c"))
-        .compile("tree-sitter-typescript");
-
-    println!("cargo:rerun-if-changed=vendor/tree-sitter-typescript/typescript/src/scanner.c");
-    cc::Build::new()
-        .include(&typescript_dir)
-        .warnings(false)
-        .file(typescript_dir.join("scanner.c"))
-        .compile("tree_sitter_typescript_scanner");
+    if enabled("typescript") {
+        let typescript_dir: PathBuf = ["vendor", "tree-sitter-typescript", "typescript", "src"]
+            .iter()
+            .collect();
+
+        println!("cargo:rerun-if-changed=vendor/tree-sitter-typescript/typescript/src/parser.c");
+        cc::Build::new()
+            .include(&typescript_dir)
+            .warnings(false)
+            .file(typescript_dir.join("parser.c"))
+            .compile("tree-sitter-typescript");
+
+        println!("cargo:rerun-if-changed=vendor/tree-sitter-typescript/typescript/src/scanner.c");
+        cc::Build::new()
+            .include(&typescript_dir)
+            .warnings(false)
+            .file(typescript_dir.join("scanner.c"))
+            .compile("tree_sitter_typescript_scanner");
+    }
+
+    // yaml
+    if enabled("yaml") {
+        let yaml_dir: PathBuf = ["vendor", "tree-sitter-yaml", "src"].iter().collect();
+
+        println!("cargo:rerun-if-changed=vendor/tree-sitter-yaml/src/parser.c");
+        cc::Build::new()
+            .include(&yaml_dir)
+            .warnings(false)
+            .file(yaml_dir.join("parser.c"))
+            .compile("tree-sitter-yaml");
+
+        println!("cargo:rerun-if-changed=vendor/tree-sitter-yaml/src/scanner.c");
+        cc::Build::new()
+            .include(&yaml_dir)
+            .warnings(false)
+            .file(yaml_dir.join("scanner.c"))
+            .compile("tree_sitter_yaml_scanner");
+    }
+}
+
+/// Each language has a cargo feature (all on by default) so builds that only
+/// need a few of them don't have to compile every grammar.
+fn enabled(feature: &str) -> bool {
+    env::var_os(format!("CARGO_FEATURE_{}", feature.to_uppercase())).is_some()
 }
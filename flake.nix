@@ -5,12 +5,39 @@
     naersk.url = "github:nmattia/naersk";
     nixpkgs.url = "github:NixOS/nixpkgs/nixpkgs-unstable";
 
-    # tree-sitter grammars
+    # tree-sitter grammars. The latest revisions of some of these need a
+    # newer tree-sitter than ours, so the ones that aren't locked from
+    # before that are pinned to a release we can load (language ABI 13 or
+    # 14.) The release is noted above each URL.
+    tree-sitter-bash = {
+      # 0.19.0
+      url = "github:tree-sitter/tree-sitter-bash/1de159fdd1659fbeb8e455302528934a750d6a0b";
+      flake = false;
+    };
+
+    tree-sitter-c = {
+      # 0.20.1
+      url = "github:tree-sitter/tree-sitter-c/e348e8ec5efd3aac020020e4af53d2ff18f393a9";
+      flake = false;
+    };
+
+    tree-sitter-c-sharp = {
+      # 0.23.1
+      url = "github:tree-sitter/tree-sitter-c-sharp/362a8a41b265056592a0c3771664a21d23a71392";
+      flake = false;
+    };
+
     tree-sitter-cpp = {
       url = "github:tree-sitter/tree-sitter-cpp";
       flake = false;
     };
 
+    tree-sitter-css = {
+      # 0.19.0
+      url = "github:tree-sitter/tree-sitter-css/4f5a2488215ea2e097c5930b351a2f2c5ea9956c";
+      flake = false;
+    };
+
     tree-sitter-elixir = {
       url = "github:elixir-lang/tree-sitter-elixir/main";
       flake = false;
@@ -21,21 +48,51 @@
       flake = false;
     };
 
+    tree-sitter-go = {
+      # 0.20.0
+      url = "github:tree-sitter/tree-sitter-go/bbaa67a180cfe0c943e50c55130918be8efb20bd";
+      flake = false;
+    };
+
     tree-sitter-haskell = {
       url = "github:tree-sitter/tree-sitter-haskell";
       flake = false;
     };
 
+    tree-sitter-html = {
+      # 0.19.0
+      url = "github:tree-sitter/tree-sitter-html/ff11d1aeefa22895ee98a93f021b541d597279c7";
+      flake = false;
+    };
+
+    tree-sitter-java = {
+      # 0.20.0
+      url = "github:tree-sitter/tree-sitter-java/09d650def6cdf7f479f4b78f595e9ef5b58ce31e";
+      flake = false;
+    };
+
     tree-sitter-javascript = {
       url = "github:tree-sitter/tree-sitter-javascript";
       flake = false;
     };
 
+    tree-sitter-json = {
+      # 0.19.0
+      url = "github:tree-sitter/tree-sitter-json/8dcda1ff216deba5a34be77aa25ff74e0094c93e";
+      flake = false;
+    };
+
     tree-sitter-php = {
       url = "github:tree-sitter/tree-sitter-php";
       flake = false;
     };
 
+    tree-sitter-python = {
+      # 0.20.4
+      url = "github:tree-sitter/tree-sitter-python/c01fb4e38587e959b9058b8cd34b9e6a3068c827";
+      flake = false;
+    };
+
     tree-sitter-ruby = {
       url = "github:tree-sitter/tree-sitter-ruby";
       flake = false;
@@ -50,6 +107,12 @@
       url = "github:tree-sitter/tree-sitter-typescript";
       flake = false;
     };
+
+    tree-sitter-yaml = {
+      # 0.6.1
+      url = "github:tree-sitter-grammars/tree-sitter-yaml/08ab1fbc18beac06b2938495a2c6ab17b5a6abc5";
+      flake = false;
+    };
   };
 
   outputs = inputs:
@@ -66,15 +129,25 @@
           mkdir vendor
 
           set -x
+          ln -s ${inputs.tree-sitter-bash} vendor/tree-sitter-bash
+          ln -s ${inputs.tree-sitter-c} vendor/tree-sitter-c
+          ln -s ${inputs.tree-sitter-c-sharp} vendor/tree-sitter-c-sharp
           ln -s ${inputs.tree-sitter-cpp} vendor/tree-sitter-cpp
+          ln -s ${inputs.tree-sitter-css} vendor/tree-sitter-css
           ln -s ${inputs.tree-sitter-elixir} vendor/tree-sitter-elixir
           ln -s ${inputs.tree-sitter-elm} vendor/tree-sitter-elm
+          ln -s ${inputs.tree-sitter-go} vendor/tree-sitter-go
           ln -s ${inputs.tree-sitter-haskell} vendor/tree-sitter-haskell
+          ln -s ${inputs.tree-sitter-html} vendor/tree-sitter-html
+          ln -s ${inputs.tree-sitter-java} vendor/tree-sitter-java
           ln -s ${inputs.tree-sitter-javascript} vendor/tree-sitter-javascript
+          ln -s ${inputs.tree-sitter-json} vendor/tree-sitter-json
           ln -s ${inputs.tree-sitter-php} vendor/tree-sitter-php
+          ln -s ${inputs.tree-sitter-python} vendor/tree-sitter-python
           ln -s ${inputs.tree-sitter-ruby} vendor/tree-sitter-ruby
           ln -s ${inputs.tree-sitter-rust} vendor/tree-sitter-rust
           ln -s ${inputs.tree-sitter-typescript} vendor/tree-sitter-typescript
+          ln -s ${inputs.tree-sitter-yaml} vendor/tree-sitter-yaml
         '';
       in rec {
         # `nix build`
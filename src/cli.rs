@@ -1,31 +1,35 @@
-use crate::extractor::Extractor;
-use crate::extractor_chooser::ExtractorChooser;
-use crate::language::Language;
+use crate::config::{self, Config, GrammarConfig};
+use crate::lines::{Coloring, LinesOpts, Multiline};
+use crate::tree::{LineRange, ShowTreeOpts, TreeFormat};
 use anyhow::{bail, Context, Error, Result};
 use clap::{crate_authors, crate_version, Arg, ArgMatches, Command};
 use itertools::Itertools;
-use std::collections::HashMap;
+use std::env;
+use std::io::{self, Read};
 use std::path::PathBuf;
 use std::str::FromStr;
+use std::time::Duration;
+use tree_grepper::{Coordinates, FileTypes, Language, Rewrite, Search, SearchBuilder, Severity};
 
 pub enum Invocation {
     DoQuery(QueryOpts),
     ShowLanguages,
+    ShowTypes(FileTypes),
+    ShowTree(ShowTreeOpts),
 }
 
 #[derive(Debug)]
 pub struct QueryOpts {
-    pub extractors: Vec<Extractor>,
-    pub paths: Vec<PathBuf>,
-    pub git_ignore: bool,
+    pub search: Search,
     pub format: QueryFormat,
+    pub lines: LinesOpts,
+    pub mode: OutputMode,
     pub sort: bool,
-}
-
-impl QueryOpts {
-    pub fn extractor_chooser(&self) -> Result<ExtractorChooser> {
-        ExtractorChooser::from_extractors(&self.extractors)
-    }
+    pub strict: bool,
+    pub quiet: bool,
+    /// When searching with rules, the severity at which a match counts as a
+    /// failure (exit status 1.)
+    pub fail_on: Option<Severity>,
 }
 
 impl Invocation {
@@ -50,12 +54,46 @@ impl Invocation {
                     .long("query")
                     .help("a language and query to perform")
                     .long_help(
-                        "a language and query to perform (at least one is required.) See https://tree-sitter.github.io for information on writing queries. Run tree-grepper --languages for a list of languages.",
+                        "a language and query to perform (at least one is required.) See https://tree-sitter.github.io for information on writing queries. Run tree-grepper --languages for a list of languages. Give a label after the language (like `rust:unwraps`) to tell matches from different queries apart; otherwise, they're numbered from 0 in the order you gave them.",
                     )
                     .number_of_values(2)
                     .value_names(&["LANGUAGE", "QUERY"])
-                    .required_unless_present("languages")
-                    .multiple_values(true)
+                    .required_unless_present_any(["languages", "query-file", "rules", "type-list", "show-tree"])
+                    .multiple_occurrences(true)
+            )
+            .arg(
+                Arg::new("query-file")
+                    .long("query-file")
+                    .takes_value(true)
+                    .value_name("PATH")
+                    .multiple_occurrences(true)
+                    .help("a .scm file (or a directory of them) with queries to perform")
+                    .long_help("a .scm file with queries to perform, or a directory of them to use as a rule set. We figure out the language of each file from a `; language: NAME` comment at the top, a second extension (like `no-unwrap.rust.scm`), or the name of the directory it's in (like `rust/no-unwrap.scm`.) JSON output says which file each match came from.")
+            )
+            .arg(
+                Arg::new("rules")
+                    .long("rules")
+                    .takes_value(true)
+                    .value_name("PATH")
+                    .multiple_occurrences(true)
+                    .help("a rule pack (.toml, .yaml, or .json) to check")
+                    .long_help("a rule pack (.toml, .yaml, or .json) to check. Each rule has an `id`, `language`, `query`, `message`, and optionally a `severity` (info, warning, or error; warning if not given) and a `fix` with a `capture` and `template` to rewrite it with. Matches are printed with their rule's message, and the exit status depends on --fail-on.")
+            )
+            .arg(
+                Arg::new("fail-on")
+                    .long("fail-on")
+                    .possible_values(["info", "warning", "error"])
+                    .default_value("error")
+                    .value_name("SEVERITY")
+                    .help("with --rules, exit with status 1 if any match is at least this severe")
+            )
+            .arg(
+                Arg::new("fix")
+                    .long("fix")
+                    .requires("rules")
+                    .conflicts_with_all(&["count", "files-with-matches", "files-without-match", "group-by-match", "quiet"])
+                    .help("with --rules, apply the fixes from matching rules, printing a diff of the changes")
+                    .long_help("with --rules, apply the fixes from matching rules the way --rewrite does, printing a diff of the changes. Pass --in-place to actually change the files.")
             )
             .arg(
                 Arg::new("no-gitignore")
@@ -65,102 +103,674 @@ impl Invocation {
             .arg(
                 Arg::new("PATHS")
                     .default_value(".")
-                    .help("places to search for matches")
+                    .help("places to search for matches (`-` means stdin)")
                     .multiple_values(true)
             )
+            .arg(
+                Arg::new("stdin")
+                .long("stdin")
+                .help("search source read from stdin (same as giving `-` as a path)")
+                .long_help("search source read from stdin (same as giving `-` as a path.) Unless you also give paths, we won't search the current directory. Use --lang or --stdin-filename to say what language the source is in.")
+            )
+            .arg(
+                Arg::new("lang")
+                .long("lang")
+                .takes_value(true)
+                .value_name("LANGUAGE")
+                .help("the language of the source on stdin")
+            )
+            .arg(
+                Arg::new("stdin-filename")
+                .long("stdin-filename")
+                .takes_value(true)
+                .value_name("PATH")
+                .help("a file name for the source on stdin, used in output and to pick its language if --lang isn't given")
+            )
             .arg(
                 Arg::new("FORMAT")
                 .long("format")
                 .short('f')
-                .possible_values(&["lines", "json", "json-lines", "pretty-json"])
+                .possible_values(["lines", "json", "json-lines", "pretty-json", "sarif"])
                 .default_value("lines")
                 .help("what format should we output lines in?")
             )
+            .arg(
+                Arg::new("after-context")
+                .long("after-context")
+                .short('A')
+                .takes_value(true)
+                .value_name("NUM")
+                .help("with lines output, show NUM lines of source after each match")
+            )
+            .arg(
+                Arg::new("before-context")
+                .long("before-context")
+                .short('B')
+                .takes_value(true)
+                .value_name("NUM")
+                .help("with lines output, show NUM lines of source before each match")
+            )
+            .arg(
+                Arg::new("context")
+                .long("context")
+                .short('C')
+                .takes_value(true)
+                .value_name("NUM")
+                .help("with lines output, show NUM lines of source before and after each match")
+            )
+            .arg(
+                Arg::new("full-lines")
+                .long("full-lines")
+                .help("with lines output, show the full source lines each match spans")
+            )
+            .arg(
+                Arg::new("multiline")
+                .long("multiline")
+                .possible_values(["keep", "escape", "truncate"])
+                .default_value("escape")
+                .help("with lines output, what to do with match text that spans multiple lines")
+                .long_help("with lines output, what to do with match text that spans multiple lines. `escape` prints newlines as `\\n` (and backslashes as `\\\\`) so that each match stays on one line, `truncate` prints only the first line followed by `...`, and `keep` prints it as-is.")
+            )
+            .arg(
+                Arg::new("ranges")
+                .long("ranges")
+                .help("with lines output, print where each match ends as well as where it starts, like `row:column-row:column`")
+            )
+            .arg(
+                Arg::new("byte-offset")
+                .long("byte-offset")
+                .short('b')
+                .help("with lines output, print the byte offset of each match after its position (or its byte range, with --ranges)")
+            )
+            .arg(
+                Arg::new("show-query")
+                .long("show-query")
+                .help("with lines output, print which query each match came from (its label, or its index) before the capture name")
+            )
+            .arg(
+                Arg::new("coordinates")
+                .long("coordinates")
+                .possible_values(["one-based", "zero-based"])
+                .default_value("one-based")
+                .help("count rows and columns from 1 (like grep) or from 0 (like tree-sitter and LSP)")
+                .long_help("count rows and columns from 1 (like grep) or from 0 (like tree-sitter and LSP.) This applies to every output format. Byte offsets always count from 0, and ends are always exclusive.")
+            )
+            .arg(
+                Arg::new("color")
+                .long("color")
+                .possible_values(["always", "never", "auto"])
+                .default_value("auto")
+                .value_name("WHEN")
+                .help("with lines output, when to color file names, positions, capture names and matches")
+                .long_help("with lines output, when to color file names, positions, capture names and matches. `auto` colors output only when printing to a terminal (and respects the `NO_COLOR` environment variable.) Combine with --full-lines or --context to see matches highlighted in their source lines.")
+            )
+            .arg(
+                Arg::new("count")
+                .long("count")
+                .short('c')
+                .help("print how many matches there are in each file, and in total, instead of the matches")
+            )
+            .arg(
+                Arg::new("by-capture")
+                .long("by-capture")
+                .requires("count")
+                .help("with --count, count each capture name separately")
+            )
+            .arg(
+                Arg::new("files-with-matches")
+                .long("files-with-matches")
+                .short('l')
+                .conflicts_with_all(&["count", "files-without-match"])
+                .help("print only the names of files with matches")
+            )
+            .arg(
+                Arg::new("files-without-match")
+                .long("files-without-match")
+                .short('L')
+                .conflicts_with_all(&["count", "files-with-matches"])
+                .help("print only the names of files we searched but didn't find any matches in")
+            )
+            .arg(
+                Arg::new("group-by-match")
+                .long("group-by-match")
+                .conflicts_with_all(&["count", "files-with-matches", "files-without-match"])
+                .help("print each query match with all its captures together, instead of one capture at a time")
+                .long_help("print each query match with all its captures together, instead of one capture at a time. In JSON output, each match has a `match_id` (counting from 0 in each file), the `pattern` in the query it matched, and a map of `captures` from each name to a list of captured nodes (a list, since quantifiers like `(_)+ @item` can capture several.) In lines output, each match gets a header line followed by one indented line per capture.")
+            )
+            .arg(
+                Arg::new("report-parse-errors")
+                .long("report-parse-errors")
+                .conflicts_with_all(&["count", "files-with-matches", "files-without-match", "group-by-match", "rewrite", "fix", "quiet"])
+                .help("print where the files we search have syntax errors, instead of matches")
+                .long_help("print where the files we search have syntax errors, instead of matches. We list every ERROR node (source the parser couldn't make sense of) and MISSING node (something the parser assumed was there so it could keep going) in each file with errors, including in code found with --injections. Matches in these files might be missing or wrong. Exits with status 0 if any file had errors, and 1 if none did.")
+            )
+            .arg(
+                Arg::new("skip-files-with-errors")
+                .long("skip-files-with-errors")
+                .conflicts_with("report-parse-errors")
+                .help("don't show matches from files with syntax errors")
+            )
+            .arg(
+                Arg::new("max-filesize")
+                .long("max-filesize")
+                .takes_value(true)
+                .value_name("NUM+SUFFIX?")
+                .help("skip files bigger than this")
+                .long_help("skip files bigger than this, without reading them. The size is in bytes, or kilobytes, megabytes, or gigabytes with a K, M, or G suffix (like `2M`.) Skipped files are listed separately from errors (on stderr for text output, or as `{\"file\": ..., \"skipped\": ...}` records in JSON output) and don't change the exit code.")
+            )
+            .arg(
+                Arg::new("parse-timeout")
+                .long("parse-timeout")
+                .takes_value(true)
+                .value_name("MILLISECONDS")
+                .help("skip files that take longer than this to parse")
+                .long_help("skip files that take longer than this to parse, like huge minified bundles. Skipped files are listed separately from errors (on stderr for text output, or as `{\"file\": ..., \"skipped\": ...}` records in JSON output) and don't change the exit code.")
+            )
+            .arg(
+                Arg::new("rewrite")
+                .long("rewrite")
+                .number_of_values(2)
+                .value_names(&["CAPTURE", "TEMPLATE"])
+                .multiple_occurrences(true)
+                .conflicts_with_all(&["count", "files-with-matches", "files-without-match", "group-by-match", "quiet"])
+                .help("replace a capture with a template, printing a diff of the changes")
+                .long_help("replace every node captured as CAPTURE with TEMPLATE, and print a unified diff of the changes (in lines output) or the edits we would make (in JSON output.) The template can use other captures from the same match: `@name` or `@{name}` (needed for names with dots, like `@{function.name}`) is replaced with that capture's text, and `@@` is a literal `@`. Files where two different edits would overlap are reported as errors and left alone. Pass --in-place to actually change the files.")
+            )
+            .arg(
+                Arg::new("in-place")
+                .long("in-place")
+                .help("with --rewrite or --fix, change the files instead of printing a diff")
+            )
             .arg(
                 Arg::new("sort")
                 .long("sort")
                 .help("sort matches stably")
                 .long_help("sort matches stably. If this is not specified, output ordering will vary because due to parallelism. Caution: this adds a worst-case `O(n * log(n))` overhead, where `n` is the number of files matched. Avoid it if possible if you care about performance.")
             )
+            .arg(
+                Arg::new("strict")
+                .long("strict")
+                .help("stop at the first file we can't search")
+                .long_help("stop at the first file we can't search. If this is not specified, we report files we can't read or parse (to stderr for text output, or as error records for JSON output) and keep going with the rest.")
+            )
+            .arg(
+                Arg::new("quiet")
+                .long("quiet")
+                .help("don't print anything, just exit 0 at the first match")
+                .long_help("don't print matches. Instead, stop at the first one and exit with status 0. If there are no matches, exit with status 1. Useful in shell conditionals and CI checks.")
+            )
             .arg(
                 Arg::new("languages")
                 .long("languages")
                 .help("print the language names tree-grepper knows about")
             )
+            .arg(
+                Arg::new("type-add")
+                .long("type-add")
+                .takes_value(true)
+                .value_name("LANGUAGE:GLOB")
+                .multiple_occurrences(true)
+                .help("also search files matching GLOB with LANGUAGE, like `javascript:*.mjs` or `ruby:Gemfile`")
+                .long_help("also search files matching GLOB with LANGUAGE, like `javascript:*.mjs` or `ruby:Gemfile`. If another language was going to be used for the same glob (like `cpp:*.h`, which C also uses) it won't be any more. Run tree-grepper --type-list to see which files each language is used for.")
+            )
+            .arg(
+                Arg::new("type-clear")
+                .long("type-clear")
+                .takes_value(true)
+                .value_name("LANGUAGE")
+                .multiple_occurrences(true)
+                .help("don't search the files LANGUAGE is usually used for (only the ones from --type-add)")
+            )
+            .arg(
+                Arg::new("no-detect-language")
+                .long("no-detect-language")
+                .help("only use file names to choose a language, not shebangs or modelines")
+                .long_help("only use file names to choose a language. By default, files whose names don't match any file type (like scripts without extensions) are searched if their first line is a shebang like `#!/usr/bin/env ruby`, or they have an Emacs (`-*- mode: ruby -*-`) or Vim (`vim: ft=ruby`) modeline.")
+            )
+            .arg(
+                Arg::new("injections")
+                .long("injections")
+                .help("also search code embedded in other languages, like <script> tags in HTML")
+                .long_help("also search code embedded in other languages: JavaScript in <script> tags and CSS in <style> tags in HTML, and the HTML around PHP. Matches in embedded code are reported with the host file's positions. Use --injection to add your own.")
+            )
+            .arg(
+                Arg::new("injection")
+                .long("injection")
+                .number_of_values(2)
+                .value_names(&["HOST", "QUERY"])
+                .multiple_occurrences(true)
+                .help("search code embedded in HOST files where an injection query finds it")
+                .long_help("search code embedded in HOST files where an injection query finds it. The query captures the embedded code as @injection.content, and says what language it's in with either `(#set! injection.language \"NAME\")` or an @injection.language capture of the language's name. Add `(#set! injection.combined)` to parse all of a file's matches as one piece of code. This works with or without --injections.")
+            )
+            .arg(
+                Arg::new("type-list")
+                .long("type-list")
+                .help("print the globs for the files we search with each language, after --type-add and --type-clear")
+            )
+            .arg(
+                Arg::new("show-tree")
+                .long("show-tree")
+                .number_of_values(2)
+                .value_names(&["LANGUAGE", "FILE"])
+                .help("print the syntax tree of FILE (or `-` for stdin), to help with writing queries")
+                .long_help("print the syntax tree of FILE (or `-` for stdin), to help with writing queries. By default, each named node gets its own line, indented by depth, with its field name, where it starts and ends (see --coordinates), and the text of nodes without named children. Pass --sexp to print an S-expression instead, in the syntax queries use.")
+            )
+            .arg(
+                Arg::new("sexp")
+                .long("sexp")
+                .requires("show-tree")
+                .help("with --show-tree, print the tree as an S-expression")
+            )
+            .arg(
+                Arg::new("line-range")
+                .long("line-range")
+                .takes_value(true)
+                .value_name("START:END")
+                .requires("show-tree")
+                .help("with --show-tree, only print nodes on these lines (or just START)")
+            )
+            .arg(
+                Arg::new("grammar")
+                .long("grammar")
+                .takes_value(true)
+                .value_name("NAME=PATH")
+                .multiple_occurrences(true)
+                .help("load a tree-sitter grammar from a shared library, and use it as the language NAME")
+                .long_help("load a tree-sitter grammar from a shared library (like `kotlin=libtree-sitter-kotlin.so`) and use it as the language NAME. We get the language from the library's `tree_sitter_NAME` function, and use it for the files in the file type called NAME (or `*.NAME` if there isn't one.) Use a config file to set a different function or different files.")
+            )
+            .arg(
+                Arg::new("config")
+                .long("config")
+                .takes_value(true)
+                .value_name("PATH")
+                .help("read settings from a TOML config file (or the file in $TREE_GREPPER_CONFIG)")
+                .long_help("read settings from a TOML config file. If this isn't given, we use the file in $TREE_GREPPER_CONFIG, if that's set. The config file can load grammars: each entry in `[[grammars]]` has a `name` and a `path` to a shared library, plus optionally the `symbol` to get the language from (`tree_sitter_NAME` by default) and a list of `globs` for the files to use it for. It can also have a list of languages in `type-clear`, and a `[type-add]` table from languages to lists of globs, which work like --type-clear and --type-add.")
+            )
             .try_get_matches_from(args)
             .context("could not parse args")?;
 
+        let mut config = Self::config(&matches)?;
+
+        // grammars have to be loaded before anything tries to look up a
+        // language by name, including --languages.
+        Self::grammars(&matches, std::mem::take(&mut config.grammars))?;
+        let file_types = Self::file_types(&matches, &config)?;
+
         if matches.is_present("languages") {
             Ok(Self::ShowLanguages)
+        } else if matches.is_present("type-list") {
+            Ok(Self::ShowTypes(file_types))
+        } else if matches.is_present("show-tree") {
+            Ok(Self::ShowTree(Self::show_tree(&matches)?))
         } else {
-            Ok(Self::DoQuery(QueryOpts {
-                extractors: Self::extractors(&matches)?,
-                paths: Self::paths(&matches)?,
-                git_ignore: !matches.is_present("no-gitignore"),
-                format: QueryFormat::from_str(
-                    matches.value_of("FORMAT").context("format not provided")?,
+            let mode = Self::mode(&matches);
+            let lines = Self::lines(&matches)?;
+            let format =
+                QueryFormat::from_str(matches.value_of("FORMAT").context("format not provided")?)
+                    .context("could not set format")?;
+
+            if matches!(format, QueryFormat::Sarif) && !matches!(mode, OutputMode::Matches) {
+                bail!("SARIF output can only show matches, not counts, file names, grouped matches, or rewrites")
+            }
+
+            if matches.is_present("in-place") && !matches!(mode, OutputMode::Rewrite { .. }) {
+                bail!("--in-place only makes sense with --rewrite or --fix")
+            }
+
+            if matches.is_present("in-place") && Self::reads_stdin(&matches) {
+                bail!("I can't rewrite stdin in place")
+            }
+
+            for stdin_only in ["lang", "stdin-filename"] {
+                if matches.is_present(stdin_only) && !Self::reads_stdin(&matches) {
+                    bail!(
+                        "--{} only makes sense when searching stdin (with `-` or --stdin)",
+                        stdin_only
+                    )
+                }
+            }
+
+            let search = Self::rewrites(&matches, Self::queries(&matches, Search::builder())?)?;
+            let search = Self::injections(&matches, search)?;
+            let search = Self::stdin(&matches, search)?
+                .paths(Self::paths(&matches)?)
+                .file_types(file_types)
+                .git_ignore(!matches.is_present("no-gitignore"))
+                .detect_language(!matches.is_present("no-detect-language"))
+                .capture_text(mode.needs_text())
+                .keep_source(
+                    lines.needs_source()
+                        || matches!(mode, OutputMode::Rewrite { .. })
+                        // SARIF needs the source to count columns in UTF-16
+                        || matches!(format, QueryFormat::Sarif),
                 )
-                .context("could not set format")?,
+                .coordinates(
+                    Coordinates::from_str(
+                        matches
+                            .value_of("coordinates")
+                            .context("coordinates not provided")?,
+                    )
+                    .context("could not set coordinates")?,
+                )
+                .include_unmatched_files(matches!(mode, OutputMode::FilesWithoutMatch))
+                .report_parse_errors(matches!(mode, OutputMode::ParseErrors))
+                .skip_files_with_errors(matches.is_present("skip-files-with-errors"))
+                .max_filesize(Self::max_filesize(&matches)?)
+                .parse_timeout(Self::parse_timeout(&matches)?)
+                .build()
+                .context("could not set up the search")?;
+
+            Ok(Self::DoQuery(QueryOpts {
+                search,
+                lines,
+                mode,
+                format,
                 sort: matches.is_present("sort"),
+                strict: matches.is_present("strict"),
+                quiet: matches.is_present("quiet"),
+                fail_on: Self::fail_on(&matches)?,
             }))
         }
     }
 
-    fn extractors(matches: &ArgMatches) -> Result<Vec<Extractor>> {
+    fn config(matches: &ArgMatches) -> Result<Config> {
+        match matches.value_of("config") {
+            Some(path) => Config::load(path.as_ref()),
+            None => match env::var_os(config::CONFIG_ENV_VAR) {
+                Some(path) if !path.is_empty() => Config::load(path.as_ref()),
+                _ => Ok(Config::default()),
+            },
+        }
+    }
+
+    fn grammars(matches: &ArgMatches, from_config: Vec<GrammarConfig>) -> Result<()> {
+        for grammar in from_config {
+            grammar.register()?;
+        }
+
+        if let Some(raw_grammars) = matches.values_of("grammar") {
+            for raw_grammar in raw_grammars {
+                GrammarConfig::from_arg(raw_grammar)?.register()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// File type changes from the config file come before the ones on the
+    /// command line, although all the clears happen before any of the adds
+    /// either way.
+    fn file_types(matches: &ArgMatches, config: &Config) -> Result<FileTypes> {
+        let mut file_types = FileTypes::new();
+
+        let clears = config
+            .type_clear
+            .iter()
+            .map(String::as_str)
+            .chain(matches.values_of("type-clear").into_iter().flatten());
+
+        for raw_lang in clears {
+            file_types = file_types
+                .clear(Language::from_str(raw_lang).context("could not parse language to clear")?);
+        }
+
+        for (raw_lang, globs) in &config.type_add {
+            let language = Language::from_str(raw_lang).context("could not parse language")?;
+            for glob in globs {
+                file_types = file_types.add(language.clone(), glob);
+            }
+        }
+
+        if let Some(type_adds) = matches.values_of("type-add") {
+            for type_add in type_adds {
+                let (raw_lang, glob) = type_add.split_once(':').with_context(|| {
+                    format!("expected a type like LANGUAGE:GLOB, but got {}", type_add)
+                })?;
+
+                file_types = file_types.add(
+                    Language::from_str(raw_lang).context("could not parse language")?,
+                    glob,
+                );
+            }
+        }
+
+        Ok(file_types)
+    }
+
+    fn show_tree(matches: &ArgMatches) -> Result<ShowTreeOpts> {
+        let (raw_lang, path) = matches
+            .values_of("show-tree")
+            .and_then(|values| values.collect_tuple())
+            .context("show-tree needs a language and a file")?;
+
+        Ok(ShowTreeOpts {
+            language: Language::from_str(raw_lang).context("could not parse language")?,
+            path: PathBuf::from(path),
+            format: if matches.is_present("sexp") {
+                TreeFormat::Sexp
+            } else {
+                TreeFormat::Indented
+            },
+            lines: match matches.value_of("line-range") {
+                Some(raw) => Some(LineRange::from_str(raw).context("could not set line range")?),
+                None => None,
+            },
+            coordinates: Coordinates::from_str(
+                matches
+                    .value_of("coordinates")
+                    .context("coordinates not provided")?,
+            )
+            .context("could not set coordinates")?,
+        })
+    }
+
+    fn lines(matches: &ArgMatches) -> Result<LinesOpts> {
+        let context = Self::context_lines(matches, "context")?.unwrap_or(0);
+
+        Ok(LinesOpts {
+            before: Self::context_lines(matches, "before-context")?.unwrap_or(context),
+            after: Self::context_lines(matches, "after-context")?.unwrap_or(context),
+            full_lines: matches.is_present("full-lines"),
+            multiline: Multiline::from_str(
+                matches
+                    .value_of("multiline")
+                    .context("multiline mode not provided")?,
+            )
+            .context("could not set multiline mode")?,
+            ranges: matches.is_present("ranges"),
+            byte_offset: matches.is_present("byte-offset"),
+            show_query: matches.is_present("show-query"),
+            color: Coloring::from_str(matches.value_of("color").context("color not provided")?)
+                .context("could not set color")?,
+        })
+    }
+
+    /// Sizes work like ripgrep's `--max-filesize`: bytes, or with a K, M, or
+    /// G suffix.
+    fn max_filesize(matches: &ArgMatches) -> Result<Option<u64>> {
+        let raw = match matches.value_of("max-filesize") {
+            Some(raw) => raw,
+            None => return Ok(None),
+        };
+
+        let (digits, multiplier) = match raw.char_indices().last() {
+            Some((at, 'K')) | Some((at, 'k')) => (&raw[..at], 1 << 10),
+            Some((at, 'M')) | Some((at, 'm')) => (&raw[..at], 1 << 20),
+            Some((at, 'G')) | Some((at, 'g')) => (&raw[..at], 1 << 30),
+            _ => (raw, 1),
+        };
+
+        u64::from_str(digits)
+            .ok()
+            .and_then(|size| size.checked_mul(multiplier))
+            .with_context(|| format!("could not parse a file size from {}", raw))
+            .map(Some)
+    }
+
+    fn parse_timeout(matches: &ArgMatches) -> Result<Option<Duration>> {
+        match matches.value_of("parse-timeout") {
+            Some(raw) => u64::from_str(raw)
+                .with_context(|| format!("could not parse a number of milliseconds from {}", raw))
+                .map(|millis| Some(Duration::from_millis(millis))),
+            None => Ok(None),
+        }
+    }
+
+    fn context_lines(matches: &ArgMatches, name: &str) -> Result<Option<usize>> {
+        match matches.value_of(name) {
+            Some(raw) => usize::from_str(raw)
+                .with_context(|| format!("could not parse a number of lines from {}", raw))
+                .map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn mode(matches: &ArgMatches) -> OutputMode {
+        if matches.is_present("report-parse-errors") {
+            OutputMode::ParseErrors
+        } else if matches.is_present("rewrite") || matches.is_present("fix") {
+            OutputMode::Rewrite {
+                in_place: matches.is_present("in-place"),
+            }
+        } else if matches.is_present("count") {
+            OutputMode::Count {
+                by_capture: matches.is_present("by-capture"),
+            }
+        } else if matches.is_present("files-with-matches") {
+            OutputMode::FilesWithMatches
+        } else if matches.is_present("files-without-match") {
+            OutputMode::FilesWithoutMatch
+        } else if matches.is_present("group-by-match") {
+            OutputMode::GroupedMatches
+        } else {
+            OutputMode::Matches
+        }
+    }
+
+    fn queries(matches: &ArgMatches, mut builder: SearchBuilder) -> Result<SearchBuilder> {
+        if let Some(query_files) = matches.values_of("query-file") {
+            for query_file in query_files {
+                builder = builder.query_file(query_file);
+            }
+        }
+
+        if let Some(rules_files) = matches.values_of("rules") {
+            for rules_file in rules_files {
+                builder = builder.rules_file(rules_file);
+            }
+        }
+        builder = builder.fix(matches.is_present("fix"));
+
         let values = match matches.values_of("additional-query") {
             Some(values) => values,
+            None if matches.is_present("query-file") || matches.is_present("rules") => {
+                return Ok(builder)
+            }
             None => bail!("queries were required but not provided. This indicates an internal error and you should report it!"),
         };
 
-        // the most common case is going to be one query, so let's allocate
-        // that immediately...
-        let mut query_strings: HashMap<Language, String> = HashMap::with_capacity(1);
-
-        // If you have two tree-sitter queries `(one)` and `(two)`, you can
-        // join them together in a single string like `(one)(two)`. In that
-        // case, the resulting query will act like an OR and match any of the
-        // queries inside. Doing this automatically gives us an advantage:
-        // for however many queries we get on the command line, we will only
-        // ever have to run one per file, since we can combine them and you
-        // can't specify queries across multiple languages! Nobody should ever
-        // notice, except that they won't see as much of a slowdown for adding
-        // new queries to an invocation as they might expect. (Well, hopefully!)
         for (raw_lang, raw_query) in values.tuples() {
-            let lang = Language::from_str(raw_lang).context("could not parse language")?;
+            builder = match raw_lang.split_once(':') {
+                Some((raw_lang, label)) => builder.labeled_query(
+                    Language::from_str(raw_lang).context("could not parse language")?,
+                    label,
+                    raw_query,
+                ),
+                None => builder.query(
+                    Language::from_str(raw_lang).context("could not parse language")?,
+                    raw_query,
+                ),
+            };
+        }
 
-            let mut query_out = String::from(raw_query);
+        Ok(builder)
+    }
 
-            let temp_query = lang
-                .parse_query(raw_query)
-                .context("could not parse query")?;
+    fn injections(matches: &ArgMatches, mut builder: SearchBuilder) -> Result<SearchBuilder> {
+        builder = builder.default_injections(matches.is_present("injections"));
 
-            if temp_query.capture_names().is_empty() {
-                query_out.push_str("@query");
+        if let Some(values) = matches.values_of("injection") {
+            for (raw_lang, raw_query) in values.tuples() {
+                builder = builder.injection(
+                    Language::from_str(raw_lang).context("could not parse injection host")?,
+                    raw_query,
+                );
             }
+        }
 
-            if let Some(existing) = query_strings.get_mut(&lang) {
-                existing.push_str(&query_out);
-            } else {
-                query_strings.insert(lang, query_out);
+        Ok(builder)
+    }
+
+    fn fail_on(matches: &ArgMatches) -> Result<Option<Severity>> {
+        if !matches.is_present("rules") {
+            return Ok(None);
+        }
+
+        Severity::from_str(
+            matches
+                .value_of("fail-on")
+                .context("fail-on not provided")?,
+        )
+        .context("could not set fail-on")
+        .map(Some)
+    }
+
+    fn rewrites(matches: &ArgMatches, mut builder: SearchBuilder) -> Result<SearchBuilder> {
+        if let Some(values) = matches.values_of("rewrite") {
+            for (capture, template) in values.tuples() {
+                builder = builder.rewrite(
+                    Rewrite::new(capture, template)
+                        .with_context(|| format!("could not parse the rewrite for {}", capture))?,
+                );
             }
         }
 
-        let mut out = Vec::with_capacity(query_strings.len());
-        for (lang, raw_query) in query_strings {
-            let query = lang
-                .parse_query(&raw_query)
-                .context("could not parse combined query")?;
+        Ok(builder)
+    }
+
+    fn reads_stdin(matches: &ArgMatches) -> bool {
+        matches.is_present("stdin")
+            || matches
+                .values_of("PATHS")
+                .into_iter()
+                .flatten()
+                .any(|path| path == "-")
+    }
 
-            out.push(Extractor::new(lang, query))
+    fn stdin(matches: &ArgMatches, builder: SearchBuilder) -> Result<SearchBuilder> {
+        if !Self::reads_stdin(matches) {
+            return Ok(builder);
         }
 
-        Ok(out)
+        let language = match matches.value_of("lang") {
+            Some(raw_lang) => {
+                Some(Language::from_str(raw_lang).context("could not parse language")?)
+            }
+            None => None,
+        };
+
+        let mut source = Vec::new();
+        io::stdin()
+            .read_to_end(&mut source)
+            .context("could not read stdin")?;
+
+        Ok(builder.text(
+            source,
+            matches.value_of("stdin-filename").map(PathBuf::from),
+            language,
+        ))
     }
 
     fn paths(matches: &ArgMatches) -> Result<Vec<PathBuf>> {
+        // `--stdin` on its own means we don't search the default path.
+        if matches.is_present("stdin") && matches.occurrences_of("PATHS") == 0 {
+            return Ok(Vec::new());
+        }
+
         match matches.values_of("PATHS") {
             Some(values) =>
                 values
+                    .filter(|raw_path| *raw_path != "-")
                     .map(|raw_path| PathBuf::from_str(raw_path).with_context(|| format!("could not parse a path from {}", raw_path)))
                     .collect(),
 
@@ -175,6 +785,7 @@ pub enum QueryFormat {
     Json,
     JsonLines,
     PrettyJson,
+    Sarif,
 }
 
 impl FromStr for QueryFormat {
@@ -186,7 +797,31 @@ impl FromStr for QueryFormat {
             "json" => Ok(QueryFormat::Json),
             "json-lines" => Ok(QueryFormat::JsonLines),
             "pretty-json" => Ok(QueryFormat::PrettyJson),
+            "sarif" => Ok(QueryFormat::Sarif),
             _ => bail!("unknown format. See --help for valid formats."),
         }
     }
 }
+
+/// What we print about each file, independent of the format we print it in.
+#[derive(Debug)]
+pub enum OutputMode {
+    Matches,
+    GroupedMatches,
+    Count { by_capture: bool },
+    FilesWithMatches,
+    FilesWithoutMatch,
+    Rewrite { in_place: bool },
+    ParseErrors,
+}
+
+impl OutputMode {
+    /// Only printing the matches themselves needs the text of each capture.
+    /// Everything else can skip copying it out of the source.
+    pub fn needs_text(&self) -> bool {
+        matches!(
+            self,
+            OutputMode::Matches | OutputMode::GroupedMatches | OutputMode::Rewrite { .. }
+        )
+    }
+}
@@ -0,0 +1,172 @@
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tree_grepper::{DynamicGrammar, Language};
+
+/// Where to look for a config file if `--config` isn't given.
+pub const CONFIG_ENV_VAR: &str = "TREE_GREPPER_CONFIG";
+
+/// Settings that are tedious to give on every invocation, read from a TOML
+/// file: grammars to load at runtime, and which files to use each language
+/// for (like `--type-clear` and `--type-add`.)
+///
+/// ```toml
+/// type-clear = ["c"]
+///
+/// [type-add]
+/// cpp = ["*.h"]
+///
+/// [[grammars]]
+/// name = "kotlin"
+/// path = "/usr/lib/libtree-sitter-kotlin.so"
+/// symbol = "tree_sitter_kotlin" # optional, this is the default
+/// globs = ["*.kt", "*.kts"]     # optional
+/// ```
+#[derive(Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    #[serde(default)]
+    pub grammars: Vec<GrammarConfig>,
+    #[serde(default)]
+    pub type_clear: Vec<String>,
+    #[serde(default)]
+    pub type_add: BTreeMap<String, Vec<String>>,
+}
+
+#[derive(Debug, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct GrammarConfig {
+    pub name: String,
+    pub path: PathBuf,
+    pub symbol: Option<String>,
+    #[serde(default)]
+    pub globs: Vec<String>,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Config> {
+        let source = fs::read_to_string(path)
+            .with_context(|| format!("could not read config from {}", path.display()))?;
+
+        let mut config: Config = toml::from_str(&source)
+            .with_context(|| format!("could not parse config from {}", path.display()))?;
+
+        // grammar paths are relative to the config file, not to wherever
+        // we happen to be running.
+        if let Some(dir) = path.parent() {
+            for grammar in config.grammars.iter_mut() {
+                grammar.path = dir.join(&grammar.path);
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+impl GrammarConfig {
+    /// Parse a grammar given on the command line, like `kotlin=path/to.so`.
+    pub fn from_arg(raw: &str) -> Result<GrammarConfig> {
+        let (name, path) = raw
+            .split_once('=')
+            .with_context(|| format!("expected a grammar like NAME=PATH, but got {}", raw))?;
+
+        Ok(GrammarConfig {
+            name: name.to_string(),
+            path: PathBuf::from(path),
+            symbol: None,
+            globs: Vec::new(),
+        })
+    }
+
+    pub fn register(self) -> Result<Language> {
+        let GrammarConfig {
+            name,
+            path,
+            symbol,
+            globs,
+        } = self;
+
+        let grammar = DynamicGrammar::load(&name, &path, symbol.as_deref(), globs)
+            .with_context(|| format!("could not load the {} grammar", name))?;
+
+        Language::register(grammar)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grammars_from_toml() {
+        let config: Config = toml::from_str(
+            r#"
+            [[grammars]]
+            name = "kotlin"
+            path = "libtree-sitter-kotlin.so"
+            globs = ["*.kt", "*.kts"]
+
+            [[grammars]]
+            name = "nix"
+            path = "/usr/lib/nix.so"
+            symbol = "tree_sitter_nix_but_different"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.grammars,
+            vec![
+                GrammarConfig {
+                    name: "kotlin".to_string(),
+                    path: PathBuf::from("libtree-sitter-kotlin.so"),
+                    symbol: None,
+                    globs: vec!["*.kt".to_string(), "*.kts".to_string()],
+                },
+                GrammarConfig {
+                    name: "nix".to_string(),
+                    path: PathBuf::from("/usr/lib/nix.so"),
+                    symbol: Some("tree_sitter_nix_but_different".to_string()),
+                    globs: Vec::new(),
+                },
+            ]
+        )
+    }
+
+    #[test]
+    fn grammars_from_args() {
+        assert_eq!(
+            GrammarConfig::from_arg("kotlin=lib/kotlin.so").unwrap(),
+            GrammarConfig {
+                name: "kotlin".to_string(),
+                path: PathBuf::from("lib/kotlin.so"),
+                symbol: None,
+                globs: Vec::new(),
+            }
+        );
+
+        assert!(GrammarConfig::from_arg("lib/kotlin.so").is_err());
+    }
+
+    #[test]
+    fn types_from_toml() {
+        let config: Config = toml::from_str(
+            r#"
+            type-clear = ["c"]
+
+            [type-add]
+            cpp = ["*.h"]
+            ruby = ["*.rake", "Gemfile"]
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.type_clear, vec!["c".to_string()]);
+        assert_eq!(
+            config.type_add.get("ruby"),
+            Some(&vec!["*.rake".to_string(), "Gemfile".to_string()])
+        );
+    }
+}
@@ -0,0 +1,252 @@
+use crate::language::Language;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use std::str::FromStr;
+
+/// How much of each end of a file we look at. Shebangs and modelines are
+/// always on the first or last few lines, so this only has to be big enough
+/// for those lines.
+const SNIFF_BYTES: u64 = 4096;
+
+/// Vim looks for modelines in the first and last 5 lines of a file (that's
+/// the default of its `modelines` option) and so do we. Emacs only looks at
+/// the first line (or the second, after a shebang) but that's covered too.
+const MODELINE_LINES: usize = 5;
+
+/// Guess the language of a file that none of our globs matched (like a
+/// script without an extension) from a shebang, or an Emacs or Vim modeline.
+/// We only read the start and end of the file, so this is cheap enough to do
+/// for every file we'd otherwise skip.
+pub(crate) fn language_for_file(path: &Path) -> Option<Language> {
+    let mut file = File::open(path).ok()?;
+    let len = file.metadata().ok()?.len();
+
+    let mut head = Vec::new();
+    (&mut file).take(SNIFF_BYTES).read_to_end(&mut head).ok()?;
+
+    let mut tail = Vec::new();
+    if len > SNIFF_BYTES {
+        file.seek(SeekFrom::Start(
+            len.saturating_sub(SNIFF_BYTES).max(SNIFF_BYTES),
+        ))
+        .ok()?;
+        file.read_to_end(&mut tail).ok()?;
+    }
+
+    language_for_source(&head, &tail)
+}
+
+/// Like `language_for_file`, but for source we've already read. `tail` can
+/// be empty if `head` is the whole source.
+pub(crate) fn language_for_source(head: &[u8], tail: &[u8]) -> Option<Language> {
+    // binary files can't have shebangs or modelines, but they can have bytes
+    // that look like them by accident.
+    if head.contains(&0) {
+        return None;
+    }
+
+    let head = String::from_utf8_lossy(head);
+    let mut head_lines = head.lines();
+
+    let first = head_lines.next()?;
+    if let Some(language) = language_for_shebang(first) {
+        return Some(language);
+    }
+
+    let head_lines: Vec<&str> = std::iter::once(first)
+        .chain(head_lines)
+        .take(MODELINE_LINES)
+        .collect();
+
+    // Emacs only allows the mode line to come after a shebang.
+    if let Some(language) = head_lines
+        .iter()
+        .take(2)
+        .find_map(|line| language_for_emacs(line))
+    {
+        return Some(language);
+    }
+
+    let tail = if tail.is_empty() {
+        head.clone()
+    } else {
+        String::from_utf8_lossy(tail)
+    };
+    let tail_lines: Vec<&str> = tail.lines().rev().take(MODELINE_LINES).collect();
+
+    head_lines
+        .iter()
+        .chain(tail_lines.iter())
+        .find_map(|line| language_for_vim(line))
+}
+
+/// `#!/usr/bin/ruby`, `#!/usr/bin/env node`, `#!/usr/bin/env -S deno run`
+/// and so on.
+fn language_for_shebang(line: &str) -> Option<Language> {
+    let mut words = line.strip_prefix("#!")?.split_whitespace();
+
+    let mut interpreter = basename(words.next()?);
+    if interpreter == "env" {
+        // skip env's own options (like `-S`) and variable assignments to
+        // get to the real interpreter.
+        interpreter = basename(words.find(|word| !word.starts_with('-') && !word.contains('='))?);
+    }
+
+    language_for_interpreter(interpreter)
+}
+
+fn language_for_interpreter(interpreter: &str) -> Option<Language> {
+    // versioned interpreters like `python3.11` or `ruby2.7` are the same
+    // language as the unversioned ones.
+    let unversioned = interpreter.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.');
+
+    let name = match unversioned {
+        "sh" | "bash" | "dash" | "ksh" | "mksh" | "zsh" => "bash",
+        "node" | "nodejs" | "bun" => "javascript",
+        "deno" | "ts-node" | "tsx" => "typescript",
+        "python" | "pypy" => "python",
+        "ruby" | "jruby" | "rake" => "ruby",
+        "elixir" | "iex" => "elixir",
+        "runhaskell" | "runghc" => "haskell",
+        "php" => "php",
+        "rust-script" | "cargo" => "rust",
+        other => other,
+    };
+
+    Language::from_str(name).ok()
+}
+
+/// `-*- mode: ruby -*-`, or just `-*- ruby -*-`.
+fn language_for_emacs(line: &str) -> Option<Language> {
+    let start = line.find("-*-")? + 3;
+    let end = start + line[start..].find("-*-")?;
+    let vars = line[start..end].trim();
+
+    let mode = if vars.contains(':') {
+        vars.split(';').find_map(|var| {
+            let (name, value) = var.split_once(':')?;
+            if name.trim().eq_ignore_ascii_case("mode") {
+                Some(value.trim())
+            } else {
+                None
+            }
+        })?
+    } else {
+        vars
+    };
+
+    let mode = mode.to_lowercase();
+    let mode = mode.trim_end_matches("-mode").trim_end_matches("-ts");
+
+    let name = match mode {
+        "sh" | "shell-script" => "bash",
+        "js" | "js2" | "js-jsx" | "rjsx" => "javascript",
+        "c++" => "cpp",
+        "web" | "mhtml" => "html",
+        "rustic" => "rust",
+        other => other,
+    };
+
+    Language::from_str(name).ok()
+}
+
+/// `vim: set ft=ruby:`, `vim: ft=ruby`, `vi: filetype=ruby`, and so on.
+fn language_for_vim(line: &str) -> Option<Language> {
+    let start = ["vim:", "vi:", "ex:"]
+        .iter()
+        .filter_map(|marker| {
+            let at = line.find(marker)?;
+            // `vi:` has to start a word so that things like `navi:` don't
+            // count.
+            if !line[..at].ends_with(|c: char| !c.is_whitespace()) {
+                Some(at + marker.len())
+            } else {
+                None
+            }
+        })
+        .min()?;
+
+    let filetype = line[start..]
+        .split(|c: char| c.is_whitespace() || c == ':')
+        .find_map(|option| {
+            option
+                .strip_prefix("ft=")
+                .or_else(|| option.strip_prefix("filetype="))
+        })?;
+
+    let name = match filetype {
+        "sh" | "zsh" => "bash",
+        "cs" => "csharp",
+        "javascriptreact" => "javascript",
+        "typescriptreact" => "tsx",
+        other => other,
+    };
+
+    Language::from_str(name).ok()
+}
+
+fn basename(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detect(source: &str) -> Option<Language> {
+        language_for_source(source.as_bytes(), &[])
+    }
+
+    #[test]
+    #[cfg(feature = "ruby")]
+    fn shebangs() {
+        assert_eq!(detect("#!/usr/bin/ruby\nputs 1"), Some(Language::Ruby));
+        assert_eq!(detect("#!/usr/bin/env ruby\nputs 1"), Some(Language::Ruby));
+        assert_eq!(detect("#!/usr/bin/env ruby2.7 -w\n"), Some(Language::Ruby));
+        assert_eq!(
+            detect("#!/usr/bin/env -S FOO=1 ruby\n"),
+            Some(Language::Ruby)
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "javascript")]
+    fn node_is_javascript() {
+        assert_eq!(
+            detect("#!/usr/bin/env node\nconsole.log(1)"),
+            Some(Language::JavaScript)
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "python")]
+    fn emacs_modelines() {
+        assert_eq!(detect("# -*- python -*-\n"), Some(Language::Python));
+        assert_eq!(
+            detect("#!/bin/weird\n# -*- mode: Python; coding: utf-8 -*-\n"),
+            Some(Language::Python)
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "python")]
+    fn vim_modelines() {
+        assert_eq!(
+            detect("x = 1\n\n# vim: set ft=python ts=4:\n"),
+            Some(Language::Python)
+        );
+        assert_eq!(
+            language_for_source(b"x = 1\n", b"...\n# vi: filetype=python\n"),
+            Some(Language::Python)
+        );
+        assert_eq!(detect("# navi: ft=python\n"), None);
+    }
+
+    #[test]
+    fn unknown_interpreters_and_binaries_are_not_detected() {
+        assert_eq!(detect("#!/usr/bin/env cobol\n"), None);
+        assert_eq!(detect("no shebang here\n"), None);
+        assert_eq!(language_for_source(b"#!/bin/sh\n\0\0", &[]), None);
+    }
+}
@@ -0,0 +1,262 @@
+use anyhow::{bail, Context, Result};
+use lazy_static::lazy_static;
+use std::cmp::Ordering;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+/// A tree-sitter grammar loaded from a shared library at runtime, for
+/// languages that aren't compiled into tree-grepper. Grammars are compared
+/// by name, since that's how people refer to them.
+pub struct DynamicGrammar {
+    name: String,
+    path: PathBuf,
+    globs: Vec<String>,
+    language: tree_sitter::Language,
+    // the language points into the library, so we have to keep it loaded as
+    // long as the language is around. (Which, since grammars are registered
+    // for the life of the process, is forever.)
+    _library: libloading::Library,
+}
+
+impl DynamicGrammar {
+    /// Load the grammar called `name` from the shared library at `path`.
+    /// The language comes from calling `symbol`, which is usually something
+    /// like `tree_sitter_NAME` (and that's what we look for if `symbol`
+    /// isn't given.) `globs` are the files to use the grammar for, like
+    /// `*.kt`. If there aren't any, we'll use the files for the file type
+    /// with the same name as the grammar, or `*.NAME` if there isn't one.
+    pub fn load(
+        name: &str,
+        path: &Path,
+        symbol: Option<&str>,
+        globs: Vec<String>,
+    ) -> Result<DynamicGrammar> {
+        if name.is_empty() {
+            bail!("grammars need a name")
+        }
+
+        let symbol = match symbol {
+            Some(symbol) => symbol.to_string(),
+            None => format!("tree_sitter_{}", name.replace('-', "_")),
+        };
+
+        // Safety: loading a library runs its initializers, and calling the
+        // symbol trusts that it's really a tree-sitter language function. We
+        // can't check either of those, so this is only as safe as the library
+        // the user pointed us at (same as any other plugin system.)
+        let (library, language) = unsafe {
+            let library = libloading::Library::new(path)
+                .with_context(|| format!("could not load a grammar from {}", path.display()))?;
+
+            let language = {
+                let constructor: libloading::Symbol<
+                    unsafe extern "C" fn() -> tree_sitter::Language,
+                > = library
+                    .get(symbol.as_bytes())
+                    .with_context(|| format!("could not find {} in {}", symbol, path.display()))?;
+                constructor()
+            };
+
+            (library, language)
+        };
+
+        // a grammar generated by a tree-sitter CLI that's much newer or older
+        // than our tree-sitter library can't be used to parse anything, so it's
+        // better to find out now than when we try to parse the first file.
+        let version = language.version();
+        if !(tree_sitter::MIN_COMPATIBLE_LANGUAGE_VERSION..=tree_sitter::LANGUAGE_VERSION)
+            .contains(&version)
+        {
+            bail!(
+                "the grammar in {} uses tree-sitter language version {}, but I can only use versions {} through {}",
+                path.display(),
+                version,
+                tree_sitter::MIN_COMPATIBLE_LANGUAGE_VERSION,
+                tree_sitter::LANGUAGE_VERSION,
+            )
+        }
+
+        Ok(DynamicGrammar {
+            name: name.to_string(),
+            path: path.to_owned(),
+            globs,
+            language,
+            _library: library,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The globs for the files this grammar should be used for. Empty means
+    /// "whatever files the file type with the grammar's name matches."
+    pub fn globs(&self) -> &[String] {
+        &self.globs
+    }
+
+    pub fn language(&self) -> tree_sitter::Language {
+        self.language
+    }
+}
+
+impl fmt::Debug for DynamicGrammar {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DynamicGrammar")
+            .field("name", &self.name)
+            .field("path", &self.path)
+            .field("globs", &self.globs)
+            .finish()
+    }
+}
+
+impl PartialEq for DynamicGrammar {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+    }
+}
+
+impl Eq for DynamicGrammar {}
+
+impl PartialOrd for DynamicGrammar {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for DynamicGrammar {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.name.cmp(&other.name)
+    }
+}
+
+impl Hash for DynamicGrammar {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.name.hash(state)
+    }
+}
+
+lazy_static! {
+    /// Every grammar we've loaded, so they can be looked up by name like the
+    /// built-in languages.
+    static ref REGISTERED: RwLock<Vec<Arc<DynamicGrammar>>> = RwLock::new(Vec::new());
+}
+
+pub(crate) fn register(grammar: DynamicGrammar) -> Result<Arc<DynamicGrammar>> {
+    let mut registered = REGISTERED
+        .write()
+        .expect("the grammar registry was poisoned");
+
+    if let Some(existing) = registered.iter().find(|g| g.name == grammar.name) {
+        // loading the same grammar twice is harmless (and happens when a
+        // grammar is both in the config file and on the command line.)
+        if existing.path == grammar.path && existing.globs == grammar.globs {
+            return Ok(Arc::clone(existing));
+        }
+
+        bail!(
+            "there's already a grammar called {} (from {})",
+            grammar.name,
+            existing.path.display()
+        )
+    }
+
+    let grammar = Arc::new(grammar);
+    registered.push(Arc::clone(&grammar));
+    Ok(grammar)
+}
+
+pub(crate) fn find(name: &str) -> Option<Arc<DynamicGrammar>> {
+    REGISTERED
+        .read()
+        .expect("the grammar registry was poisoned")
+        .iter()
+        .find(|grammar| grammar.name == name)
+        .cloned()
+}
+
+pub(crate) fn all() -> Vec<Arc<DynamicGrammar>> {
+    REGISTERED
+        .read()
+        .expect("the grammar registry was poisoned")
+        .clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_libraries_are_an_error() {
+        let err = DynamicGrammar::load(
+            "nope",
+            Path::new("/does/not/exist/libtree-sitter-nope.so"),
+            None,
+            Vec::new(),
+        )
+        .unwrap_err();
+
+        assert!(err.to_string().starts_with("could not load a grammar from"));
+    }
+
+    // libc is the one shared library we can count on being around. It
+    // doesn't have a grammar in it, of course, but that's fine for checking
+    // what happens around the grammar.
+    #[cfg(target_os = "linux")]
+    const LIBC: &str = "libc.so.6";
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn missing_symbols_are_an_error() {
+        let err = DynamicGrammar::load("nope", Path::new(LIBC), None, Vec::new()).unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            format!("could not find tree_sitter_nope in {}", LIBC)
+        );
+    }
+
+    #[cfg(all(target_os = "linux", feature = "rust"))]
+    fn grammar(name: &str, path: &str) -> DynamicGrammar {
+        DynamicGrammar {
+            name: name.to_string(),
+            path: PathBuf::from(path),
+            globs: Vec::new(),
+            language: crate::Language::Rust.language(),
+            _library: unsafe { libloading::Library::new(LIBC) }.unwrap(),
+        }
+    }
+
+    #[test]
+    #[cfg(all(target_os = "linux", feature = "rust"))]
+    fn registered_grammars_can_be_found_by_name() {
+        use crate::Language;
+        use std::str::FromStr;
+
+        let registered = Language::register(grammar("registry-test", "a.so")).unwrap();
+        assert_eq!(Language::from_str("registry-test").unwrap(), registered);
+        assert!(Language::all().contains(&registered));
+
+        // the same grammar again is fine...
+        assert_eq!(
+            Language::register(grammar("registry-test", "a.so")).unwrap(),
+            registered
+        );
+
+        // ...but a different one with the same name isn't
+        let err = Language::register(grammar("registry-test", "b.so")).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "there's already a grammar called registry-test (from a.so)"
+        );
+
+        // and neither is one that would shadow a built-in language
+        assert!(Language::register(grammar("rust", "a.so")).is_err());
+    }
+}
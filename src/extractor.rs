@@ -1,25 +1,45 @@
+use crate::injection::Injector;
 use crate::language::Language;
-use anyhow::{Context, Result};
+use crate::rewrite::{Edit, Rewrite};
+use crate::rules::{Rule, Severity};
+use anyhow::{anyhow, bail, Context, Result};
 use serde::ser::{SerializeStruct, Serializer};
 use serde::Serialize;
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashSet};
+use std::convert::TryFrom;
 use std::fmt::{self, Display};
 use std::fs;
 use std::path::{Path, PathBuf};
-use tree_sitter::{Parser, Point, Query, QueryCursor};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tree_sitter::{Parser, Point, Query, QueryCursor, Range, Tree};
 
 #[derive(Debug)]
 pub struct Extractor {
     language: Language,
     ts_language: tree_sitter::Language,
     query: Query,
-    captures: Vec<String>,
+    captures: Vec<Arc<str>>,
     ignores: HashSet<usize>,
+    capture_text: bool,
+    keep_source: bool,
+    coordinates: Coordinates,
+    rewrites: Vec<Rewrite>,
+    pattern_origins: Vec<PatternOrigin>,
+    report_parse_errors: bool,
+    skip_files_with_errors: bool,
+    max_filesize: Option<u64>,
+    parse_timeout: Option<Duration>,
 }
 
 impl Extractor {
     pub fn new(language: Language, query: Query) -> Extractor {
-        let captures = query.capture_names().to_vec();
+        let captures: Vec<Arc<str>> = query
+            .capture_names()
+            .iter()
+            .map(|name| Arc::from(name.as_str()))
+            .collect();
 
         let mut ignores = HashSet::default();
         captures.iter().enumerate().for_each(|(i, name)| {
@@ -29,14 +49,100 @@ impl Extractor {
         });
 
         Extractor {
-            ts_language: (&language).language(),
+            ts_language: language.language(),
             language,
             query,
             captures,
             ignores,
+            capture_text: true,
+            keep_source: false,
+            coordinates: Coordinates::OneBased,
+            rewrites: Vec::new(),
+            pattern_origins: Vec::new(),
+            report_parse_errors: false,
+            skip_files_with_errors: false,
+            max_filesize: None,
+            parse_timeout: None,
         }
     }
 
+    /// Where each pattern in the query came from, by pattern index. Matches
+    /// carry their pattern's origin along with them. (Default: nowhere in
+    /// particular.)
+    pub(crate) fn pattern_origins(mut self, pattern_origins: Vec<PatternOrigin>) -> Extractor {
+        self.pattern_origins = pattern_origins;
+        self
+    }
+
+    /// Should we copy the source text of each capture into the matches we
+    /// extract? (Default: yes.) If you only care about where or how many
+    /// matches there are, turning this off saves an allocation per match.
+    pub fn capture_text(mut self, capture_text: bool) -> Extractor {
+        self.capture_text = capture_text;
+        self
+    }
+
+    /// Should we keep the whole source of each file we extract matches from?
+    /// (Default: no.) This is useful for showing the matches in context, but
+    /// means holding on to a copy of every file with matches.
+    pub fn keep_source(mut self, keep_source: bool) -> Extractor {
+        self.keep_source = keep_source;
+        self
+    }
+
+    /// Should rows and columns in matches we extract count from 0 or from 1
+    /// when they're printed or serialized? (Default: from 1.)
+    pub fn coordinates(mut self, coordinates: Coordinates) -> Extractor {
+        self.coordinates = coordinates;
+        self
+    }
+
+    /// Replace captures in the files we extract from. The edits show up in
+    /// `ExtractedFile::edits`, and files with edits but without any other
+    /// matches are still extracted.
+    pub fn rewrite(mut self, rewrite: Rewrite) -> Extractor {
+        self.rewrites.push(rewrite);
+        self
+    }
+
+    /// Should we say where each syntax error is in the files we extract
+    /// from? (Default: no.) The errors show up in `ExtractedFile::parse_errors`,
+    /// and files with errors but without any matches are still extracted.
+    pub fn report_parse_errors(mut self, report_parse_errors: bool) -> Extractor {
+        self.report_parse_errors = report_parse_errors;
+        self
+    }
+
+    /// Should we skip running queries on files with syntax errors? (Default:
+    /// no.) Matches in a file that didn't parse cleanly can be missing or
+    /// wrong, so this is for when you'd rather not see them at all. Skipped
+    /// files are extracted as `None`, the same as files without matches.
+    pub fn skip_files_with_errors(mut self, skip_files_with_errors: bool) -> Extractor {
+        self.skip_files_with_errors = skip_files_with_errors;
+        self
+    }
+
+    /// Skip files bigger than this many bytes without reading them.
+    /// (Default: no limit.) Extracting from a skipped file fails with a
+    /// `Skipped` error.
+    pub fn max_filesize(mut self, max_filesize: Option<u64>) -> Extractor {
+        self.max_filesize = max_filesize;
+        self
+    }
+
+    /// Give up on files that take longer than this to parse, including any
+    /// code injected into them. (Default: no limit.) Extracting from a file
+    /// we gave up on fails with a `Skipped` error.
+    pub fn parse_timeout(mut self, parse_timeout: Option<Duration>) -> Extractor {
+        self.parse_timeout = parse_timeout;
+        self
+    }
+
+    /// Does the query have a capture with this name (without the `@`)?
+    pub fn has_capture(&self, name: &str) -> bool {
+        self.captures.iter().any(|capture| &**capture == name)
+    }
+
     pub fn language(&self) -> &Language {
         &self.language
     }
@@ -46,9 +152,36 @@ impl Extractor {
         path: &Path,
         parser: &mut Parser,
     ) -> Result<Option<ExtractedFile>> {
-        let source = fs::read(&path).context("could not read file")?;
+        self.extract_from_file_with(path, parser, Injector::default())
+    }
 
-        self.extract_from_text(Some(path), &source, parser)
+    /// Like `extract_from_file`, but also extracting from regions of other
+    /// languages injected into this one.
+    pub(crate) fn extract_from_file_with(
+        &self,
+        path: &Path,
+        parser: &mut Parser,
+        injector: Injector,
+    ) -> Result<Option<ExtractedFile>> {
+        // checking the size first means we never read (or hold on to) huge
+        // files at all.
+        if let Some(max_filesize) = self.max_filesize {
+            let size = fs::metadata(path).context("could not read file")?.len();
+            if size > max_filesize {
+                return Err(Skipped::TooBig { size, max_filesize }.into());
+            }
+        }
+
+        let source = fs::read(path).context("could not read file")?;
+
+        let mut extracted = self.extract(Some(path), &source, parser, injector)?;
+        if self.keep_source {
+            if let Some(extracted_file) = &mut extracted {
+                extracted_file.source = Some(source);
+            }
+        }
+
+        Ok(extracted)
     }
 
     pub fn extract_from_text(
@@ -57,127 +190,848 @@ impl Extractor {
         source: &[u8],
         parser: &mut Parser,
     ) -> Result<Option<ExtractedFile>> {
-        parser
-            .set_language(self.ts_language)
-            .context("could not set language")?;
-
-        let tree = parser
-            .parse(&source, None)
-            // note: this could be a timeout or cancellation, but we don't set
-            // that so we know it's always a language error. Buuuut we also
-            // always set the language above so if this happens we also know
-            // it's an internal error.
-            .context(
-                "could not parse to a tree. This is an internal error and should be reported.",
-            )?;
+        self.extract_from_text_with(path, source, parser, Injector::default())
+    }
+
+    /// Like `extract_from_text`, but also extracting from regions of other
+    /// languages injected into this one.
+    pub(crate) fn extract_from_text_with(
+        &self,
+        path: Option<&Path>,
+        source: &[u8],
+        parser: &mut Parser,
+        injector: Injector,
+    ) -> Result<Option<ExtractedFile>> {
+        if let Some(max_filesize) = self.max_filesize {
+            let size = source.len() as u64;
+            if size > max_filesize {
+                return Err(Skipped::TooBig { size, max_filesize }.into());
+            }
+        }
+
+        let mut extracted = self.extract(path, source, parser, injector)?;
+        if self.keep_source {
+            if let Some(extracted_file) = &mut extracted {
+                extracted_file.source = Some(source.to_vec());
+            }
+        }
+
+        Ok(extracted)
+    }
+
+    fn extract(
+        &self,
+        path: Option<&Path>,
+        source: &[u8],
+        parser: &mut Parser,
+        injector: Injector,
+    ) -> Result<Option<ExtractedFile>> {
+        let deadline = self.parse_timeout.map(Deadline::new);
+        let tree = parse(self.ts_language, source, parser, &[], deadline)?;
+
+        let mut found = Found::new(self.report_parse_errors, self.coordinates, deadline);
+        found.note_errors(&tree);
+        // there's no point looking inside a file we're going to skip anyway.
+        if self.skip_files_with_errors && found.has_errors {
+            return Ok(None);
+        }
+
+        self.collect(&tree, source, &mut found)?;
+        injector.extract(&self.language, &tree, source, parser, &mut found)?;
+
+        if found.matches.is_empty() && found.edits.is_empty() && found.parse_errors.is_empty() {
+            return Ok(None);
+        }
 
+        Ok(Some(ExtractedFile {
+            file: path.map(|p| p.to_owned()),
+            file_type: self.language.to_string(),
+            has_errors: found.has_errors,
+            matches: found.matches,
+            edits: Edit::check(found.edits)?,
+            parse_errors: found.parse_errors,
+            source: None,
+        }))
+    }
+
+    /// Run the query over a parsed tree, adding what it finds to `found`.
+    /// The tree doesn't have to cover the whole source (see `parse`), but
+    /// it does have to be in this extractor's language.
+    pub(crate) fn collect(&self, tree: &Tree, source: &[u8], found: &mut Found) -> Result<()> {
         let mut cursor = QueryCursor::new();
 
-        let extracted_matches = cursor
-            .matches(&self.query, tree.root_node(), source)
-            .flat_map(|query_match| query_match.captures)
-            // note: the casts here could potentially break if run on a 16-bit
-            // microcontroller. I don't think this is a huge problem, though,
-            // since even the gnarliest queries I've written have something on
-            // the order of 20 matches. Nowhere close to 2^16!
-            .filter(|capture| !self.ignores.contains(&(capture.index as usize)))
-            .map(|capture| {
-                let name = &self.captures[capture.index as usize];
+        for query_match in cursor.matches(&self.query, tree.root_node(), source) {
+            let origin = self
+                .pattern_origins
+                .get(query_match.pattern_index)
+                .cloned()
+                .unwrap_or_default();
+            let captures_before = found.matches.len();
+
+            for capture in query_match.captures {
+                // note: the casts here could potentially break if run on a
+                // 16-bit microcontroller. I don't think this is a huge
+                // problem, though, since even the gnarliest queries I've
+                // written have something on the order of 20 matches. Nowhere
+                // close to 2^16!
+                if self.ignores.contains(&(capture.index as usize)) {
+                    continue;
+                }
+
+                let name = Arc::clone(&self.captures[capture.index as usize]);
                 let node = capture.node;
-                let text = match node
-                    .utf8_text(source)
-                    .map(|unowned| unowned.to_string())
-                    .context("could not extract text from capture")
-                {
-                    Ok(text) => text,
-                    Err(problem) => return Err(problem),
+                let text = if self.capture_text {
+                    node.utf8_text(source)
+                        .context("could not extract text from capture")?
+                        .to_string()
+                } else {
+                    String::new()
                 };
 
-                Ok(ExtractedMatch {
+                found.matches.push(ExtractedMatch {
                     kind: node.kind(),
                     name,
                     text,
                     start: node.start_position(),
                     end: node.end_position(),
+                    start_byte: node.start_byte(),
+                    end_byte: node.end_byte(),
+                    coordinates: self.coordinates,
+                    match_id: found.match_id,
+                    pattern: query_match.pattern_index,
+                    query: origin.query,
+                    query_label: origin.query_label.clone(),
+                    query_file: origin.query_file.clone(),
+                    rule: origin.rule.clone(),
                 })
-            })
-            .collect::<Result<Vec<ExtractedMatch>>>()?;
+            }
 
-        if extracted_matches.is_empty() {
-            Ok(None)
-        } else {
-            Ok(Some(ExtractedFile {
-                file: path.map(|p| p.to_owned()),
-                file_type: self.language.to_string(),
-                matches: extracted_matches,
-            }))
+            // matches where every capture was ignored don't show up in the
+            // results, so they don't get an id either.
+            if found.matches.len() > captures_before {
+                found.match_id += 1;
+            }
+
+            for rewrite in &self.rewrites {
+                // fixes for one rule shouldn't touch matches from another,
+                // even if they happen to use the same capture names.
+                if let Some(rule_id) = rewrite.rule() {
+                    if origin.rule.as_ref().map(|rule| rule.id()) != Some(rule_id) {
+                        continue;
+                    }
+                }
+
+                found.edits.extend(
+                    rewrite
+                        .edits(&self.captures, &query_match, source, self.coordinates)
+                        .context("could not rewrite capture")?,
+                )
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Parse `source` as `language`. If `ranges` isn't empty, only those parts
+/// of the source are parsed, but positions in the tree are still relative
+/// to the whole source.
+pub(crate) fn parse(
+    language: tree_sitter::Language,
+    source: &[u8],
+    parser: &mut Parser,
+    ranges: &[Range],
+    deadline: Option<Deadline>,
+) -> Result<Tree> {
+    parser
+        .set_language(language)
+        .context("could not set language")?;
+
+    // parsers get reused from file to file, so we have to reset these even
+    // when we want the whole source, or all the time in the world.
+    parser
+        .set_included_ranges(ranges)
+        .map_err(|err| anyhow!("could not parse ranges: range {} is out of order", err.0))?;
+
+    match deadline {
+        Some(deadline) => {
+            let remaining = deadline.remaining()?;
+            // a timeout of 0 means no timeout, so we round up.
+            parser.set_timeout_micros(
+                u64::try_from(remaining.as_micros())
+                    .unwrap_or(u64::MAX)
+                    .max(1),
+            )
+        }
+        None => parser.set_timeout_micros(0),
+    }
+
+    match parser.parse(source, None) {
+        Some(tree) => Ok(tree),
+        None => match deadline {
+            Some(deadline) => {
+                // a parser that timed out picks up where it left off next
+                // time, which would be very confusing for the next file.
+                parser.reset();
+                Err(Skipped::Timeout(deadline.timeout).into())
+            }
+            // we always set the language above and don't use cancellation,
+            // so this can only be a bug.
+            None => bail!(
+                "could not parse to a tree. This is an internal error and should be reported."
+            ),
+        },
+    }
+}
+
+/// When we have to be done parsing a file by, including anything injected
+/// into it.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Deadline {
+    at: Instant,
+    timeout: Duration,
+}
+
+impl Deadline {
+    fn new(timeout: Duration) -> Deadline {
+        Deadline {
+            at: Instant::now() + timeout,
+            timeout,
+        }
+    }
+
+    fn remaining(&self) -> Result<Duration, Skipped> {
+        match self.at.checked_duration_since(Instant::now()) {
+            Some(remaining) if !remaining.is_zero() => Ok(remaining),
+            _ => Err(Skipped::Timeout(self.timeout)),
+        }
+    }
+}
+
+/// Why we didn't search a file, when it isn't because anything went wrong.
+/// These show up as the error in a `FileError` (see `FileError::skipped`.)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Skipped {
+    /// The file was bigger than `Extractor::max_filesize`.
+    TooBig { size: u64, max_filesize: u64 },
+    /// Parsing took longer than `Extractor::parse_timeout`.
+    Timeout(Duration),
+}
+
+impl Display for Skipped {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Skipped::TooBig { size, max_filesize } => write!(
+                f,
+                "the file is {} bytes, over the limit of {} bytes",
+                size, max_filesize
+            ),
+            Skipped::Timeout(timeout) => {
+                write!(f, "parsing took longer than {}ms", timeout.as_millis())
+            }
+        }
+    }
+}
+
+impl std::error::Error for Skipped {}
+
+/// Matches, edits, and syntax errors found so far in one file, possibly by
+/// several extractors (when languages are injected into each other.)
+#[derive(Debug)]
+pub(crate) struct Found {
+    matches: Vec<ExtractedMatch>,
+    edits: Vec<Edit>,
+    match_id: usize,
+    has_errors: bool,
+    parse_errors: Vec<ParseError>,
+    report_parse_errors: bool,
+    coordinates: Coordinates,
+    deadline: Option<Deadline>,
+}
+
+impl Found {
+    fn new(
+        report_parse_errors: bool,
+        coordinates: Coordinates,
+        deadline: Option<Deadline>,
+    ) -> Found {
+        Found {
+            matches: Vec::new(),
+            edits: Vec::new(),
+            match_id: 0,
+            has_errors: false,
+            parse_errors: Vec::new(),
+            report_parse_errors,
+            coordinates,
+            deadline,
+        }
+    }
+
+    /// When parsing anything else in this file has to be done by.
+    pub(crate) fn deadline(&self) -> Option<Deadline> {
+        self.deadline
+    }
+
+    /// Remember whether a tree has any syntax errors and, if we're reporting
+    /// them, where they are.
+    pub(crate) fn note_errors(&mut self, tree: &Tree) {
+        let root = tree.root_node();
+        if !root.has_error() {
+            return;
+        }
+
+        self.has_errors = true;
+        if !self.report_parse_errors {
+            return;
+        }
+
+        // `has_error` is true for every ancestor of an error, so we only have
+        // to look inside nodes where it's set. Once we're in an ERROR node,
+        // anything further down is part of the same error.
+        let mut cursor = root.walk();
+        loop {
+            let node = cursor.node();
+            if node.is_error() || node.is_missing() {
+                self.parse_errors.push(ParseError {
+                    missing: if node.is_missing() {
+                        Some(node.kind())
+                    } else {
+                        None
+                    },
+                    start: node.start_position(),
+                    end: node.end_position(),
+                    start_byte: node.start_byte(),
+                    end_byte: node.end_byte(),
+                    coordinates: self.coordinates,
+                });
+            } else if node.has_error() && cursor.goto_first_child() {
+                continue;
+            }
+
+            while !cursor.goto_next_sibling() {
+                if !cursor.goto_parent() {
+                    return;
+                }
+            }
         }
     }
 }
 
 #[derive(Debug, Serialize, PartialEq, Eq, PartialOrd, Ord)]
-pub struct ExtractedFile<'query> {
+pub struct ExtractedFile {
     file: Option<PathBuf>,
     file_type: String,
-    matches: Vec<ExtractedMatch<'query>>,
+    has_errors: bool,
+    matches: Vec<ExtractedMatch>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    edits: Vec<Edit>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    parse_errors: Vec<ParseError>,
+    #[serde(skip)]
+    source: Option<Vec<u8>>,
 }
 
-impl<'query> Display for ExtractedFile<'query> {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+impl ExtractedFile {
+    /// A file we searched without finding any matches.
+    pub fn without_matches(file: Option<PathBuf>, language: &Language) -> ExtractedFile {
+        ExtractedFile {
+            file,
+            file_type: language.to_string(),
+            has_errors: false,
+            matches: Vec::new(),
+            edits: Vec::new(),
+            parse_errors: Vec::new(),
+            source: None,
+        }
+    }
+
+    pub fn file(&self) -> Option<&Path> {
+        self.file.as_deref()
+    }
+
+    /// The file name, suitable for printing in line-oriented output.
+    pub fn display_name(&self) -> &str {
         // TODO: is there a better way to do this unwrapping? This implementation
         // turns non-UTF-8 paths into "NON-UTF8 FILENAME". I don't know exactly
         // what circumstances that could happen in... maybe we should just wait
         // for bug reports?
-        let filename = self
-            .file
+        self.file
             .as_ref()
             .map(|f| f.to_str().unwrap_or("NON-UTF8 FILENAME"))
-            .unwrap_or("NO FILE");
+            .unwrap_or("NO FILE")
+    }
+
+    pub fn file_type(&self) -> &str {
+        &self.file_type
+    }
+
+    pub fn matches(&self) -> &[ExtractedMatch] {
+        &self.matches
+    }
+
+    /// Did the file (or any code injected into it) have syntax errors? If
+    /// so, the matches might be incomplete or wrong.
+    pub fn has_errors(&self) -> bool {
+        self.has_errors
+    }
+
+    /// Where the syntax errors are, if the extractor was set up to report
+    /// them.
+    pub fn parse_errors(&self) -> &[ParseError] {
+        &self.parse_errors
+    }
+
+    /// The matches in this file grouped by the query match they came from,
+    /// so captures that matched together stay together.
+    pub fn grouped_matches(&self) -> Vec<GroupedMatch<'_>> {
+        let mut groups = Vec::new();
+        let mut start = 0;
+
+        for end in 1..=self.matches.len() {
+            if end == self.matches.len()
+                || self.matches[end].match_id != self.matches[start].match_id
+            {
+                groups.push(GroupedMatch {
+                    captures: &self.matches[start..end],
+                });
+                start = end;
+            }
+        }
+
+        groups
+    }
+
+    /// The full source of the file, if the extractor was set up to keep it.
+    pub fn source(&self) -> Option<&[u8]> {
+        self.source.as_deref()
+    }
+
+    /// What the extractor's rewrites would change in this file, sorted from
+    /// the start of the file to the end. These never overlap: we refuse to
+    /// extract from a file where they would.
+    pub fn edits(&self) -> &[Edit] {
+        &self.edits
+    }
+
+    /// The most severe rule any match in this file came from, if any of them
+    /// came from rules.
+    pub fn highest_severity(&self) -> Option<Severity> {
+        self.matches
+            .iter()
+            .filter_map(|extracted_match| extracted_match.rule())
+            .map(|rule| rule.severity())
+            .max()
+    }
+
+    /// The source of the file with all the edits applied, if the extractor
+    /// was set up to keep the source.
+    pub fn rewritten(&self) -> Option<Vec<u8>> {
+        self.source
+            .as_ref()
+            .map(|source| Edit::apply(source, &self.edits))
+    }
+}
+
+impl Display for ExtractedFile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let filename = self.display_name();
 
         for extraction in &self.matches {
-            writeln!(
-                f,
-                "{}:{}:{}:{}:{}",
-                filename,
-                extraction.start.row + 1,
-                extraction.start.column + 1,
-                extraction.name,
-                extraction.text
-            )?
+            let base = extraction.coordinates.base();
+
+            match &extraction.rule {
+                Some(rule) => writeln!(
+                    f,
+                    "{}:{}:{}: {}: {} [{}]",
+                    filename,
+                    extraction.start.row + base,
+                    extraction.start.column + base,
+                    rule.severity(),
+                    rule.message(),
+                    rule.id(),
+                )?,
+                None => writeln!(
+                    f,
+                    "{}:{}:{}:{}:{}",
+                    filename,
+                    extraction.start.row + base,
+                    extraction.start.column + base,
+                    extraction.name,
+                    extraction.text
+                )?,
+            }
         }
 
         Ok(())
     }
 }
 
-#[derive(Debug, Serialize, PartialEq, Eq, PartialOrd, Ord)]
-pub struct ExtractedMatch<'query> {
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ExtractedMatch {
     kind: &'static str,
-    name: &'query str,
+    name: Arc<str>,
     text: String,
-    #[serde(serialize_with = "serialize_point")]
     start: Point,
-    #[serde(serialize_with = "serialize_point")]
     end: Point,
+    start_byte: usize,
+    end_byte: usize,
+    coordinates: Coordinates,
+    match_id: usize,
+    pattern: usize,
+    query: usize,
+    query_label: Option<Arc<str>>,
+    query_file: Option<Arc<str>>,
+    rule: Option<Arc<Rule>>,
+}
+
+impl ExtractedMatch {
+    /// The kind of the captured node, like `identifier` or `import_clause`.
+    pub fn kind(&self) -> &'static str {
+        self.kind
+    }
+
+    /// The name of the capture in the query, without the leading `@`.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The source text of the capture. This is empty if the extractor was
+    /// set up not to capture text.
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Where the captured node starts. Note that this is always 0-based, no
+    /// matter what `coordinates` says.
+    pub fn start(&self) -> Point {
+        self.start
+    }
+
+    /// Where the captured node ends (exclusive.) Note that this is always
+    /// 0-based, no matter what `coordinates` says.
+    pub fn end(&self) -> Point {
+        self.end
+    }
+
+    /// The offset of the first byte of the captured node in the source.
+    pub fn start_byte(&self) -> usize {
+        self.start_byte
+    }
+
+    /// The offset of the byte just past the end of the captured node in the
+    /// source, so `&source[start_byte..end_byte]` is the captured text.
+    pub fn end_byte(&self) -> usize {
+        self.end_byte
+    }
+
+    /// How rows and columns should be counted when we show them to people.
+    pub fn coordinates(&self) -> Coordinates {
+        self.coordinates
+    }
+
+    /// Which query match in the file this capture came from. Captures from
+    /// the same match have the same id, and ids count up from 0 in each file.
+    pub fn match_id(&self) -> usize {
+        self.match_id
+    }
+
+    /// The index of the pattern in the extractor's query that produced this
+    /// match.
+    pub fn pattern(&self) -> usize {
+        self.pattern
+    }
+
+    /// The index of the query that produced this match, counting queries in
+    /// the order they were added to the search: queries first, then query
+    /// files, then rules.
+    pub fn query(&self) -> usize {
+        self.query
+    }
+
+    /// The label given to the query that produced this match, if it had one.
+    pub fn query_label(&self) -> Option<&str> {
+        self.query_label.as_deref()
+    }
+
+    /// The query file that the pattern which produced this match came from,
+    /// if it came from one.
+    pub fn query_file(&self) -> Option<&str> {
+        self.query_file.as_deref()
+    }
+
+    /// The rule that the pattern which produced this match came from, if it
+    /// came from one.
+    pub fn rule(&self) -> Option<&Rule> {
+        self.rule.as_deref()
+    }
+}
+
+impl Serialize for ExtractedMatch {
+    fn serialize<S>(&self, sz: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut out = sz.serialize_struct("ExtractedMatch", 13)?;
+        out.serialize_field("kind", &self.kind)?;
+        out.serialize_field("name", &self.name)?;
+        out.serialize_field("text", &self.text)?;
+        out.serialize_field("start", &SerializePoint(self.start, self.coordinates))?;
+        out.serialize_field("end", &SerializePoint(self.end, self.coordinates))?;
+        out.serialize_field("start_byte", &self.start_byte)?;
+        out.serialize_field("end_byte", &self.end_byte)?;
+        out.serialize_field("query", &self.query)?;
+        match &self.query_label {
+            Some(query_label) => out.serialize_field("query_label", query_label)?,
+            None => out.skip_field("query_label")?,
+        }
+        match &self.query_file {
+            Some(query_file) => out.serialize_field("query_file", query_file)?,
+            None => out.skip_field("query_file")?,
+        }
+        match &self.rule {
+            Some(rule) => {
+                out.serialize_field("rule", rule.id())?;
+                out.serialize_field("message", rule.message())?;
+                out.serialize_field("severity", &rule.severity())?;
+            }
+            None => {
+                out.skip_field("rule")?;
+                out.skip_field("message")?;
+                out.skip_field("severity")?;
+            }
+        }
+        out.end()
+    }
+}
+
+/// The captures from a single query match. Every capture in a group has the
+/// same match id, pattern, and origin.
+#[derive(Debug)]
+pub struct GroupedMatch<'file> {
+    captures: &'file [ExtractedMatch],
+}
+
+impl<'file> GroupedMatch<'file> {
+    pub fn captures(&self) -> &'file [ExtractedMatch] {
+        self.captures
+    }
+
+    /// The capture the rest of the group's information comes from. Groups
+    /// are never empty, so this is always there.
+    pub fn first(&self) -> &'file ExtractedMatch {
+        &self.captures[0]
+    }
+}
+
+impl Serialize for GroupedMatch<'_> {
+    fn serialize<S>(&self, sz: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let first = self.first();
+
+        // a capture can match more than once in a single match (with a
+        // quantifier like `(_)+ @item`), so each name maps to a list.
+        let mut captures: BTreeMap<&str, Vec<SerializeCapture>> = BTreeMap::new();
+        for capture in self.captures {
+            captures
+                .entry(capture.name())
+                .or_default()
+                .push(SerializeCapture(capture));
+        }
+
+        let mut out = sz.serialize_struct("GroupedMatch", 9)?;
+        out.serialize_field("match_id", &first.match_id)?;
+        out.serialize_field("pattern", &first.pattern)?;
+        out.serialize_field("query", &first.query)?;
+        match &first.query_label {
+            Some(query_label) => out.serialize_field("query_label", query_label)?,
+            None => out.skip_field("query_label")?,
+        }
+        match &first.query_file {
+            Some(query_file) => out.serialize_field("query_file", query_file)?,
+            None => out.skip_field("query_file")?,
+        }
+        match &first.rule {
+            Some(rule) => {
+                out.serialize_field("rule", rule.id())?;
+                out.serialize_field("message", rule.message())?;
+                out.serialize_field("severity", &rule.severity())?;
+            }
+            None => {
+                out.skip_field("rule")?;
+                out.skip_field("message")?;
+                out.skip_field("severity")?;
+            }
+        }
+        out.serialize_field("captures", &captures)?;
+        out.end()
+    }
+}
+
+/// Just the parts of a match that are specific to its capture, for
+/// `GroupedMatch`.
+struct SerializeCapture<'a>(&'a ExtractedMatch);
+
+impl Serialize for SerializeCapture<'_> {
+    fn serialize<S>(&self, sz: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let capture = self.0;
+
+        let mut out = sz.serialize_struct("Capture", 6)?;
+        out.serialize_field("kind", &capture.kind)?;
+        out.serialize_field("text", &capture.text)?;
+        out.serialize_field("start", &SerializePoint(capture.start, capture.coordinates))?;
+        out.serialize_field("end", &SerializePoint(capture.end, capture.coordinates))?;
+        out.serialize_field("start_byte", &capture.start_byte)?;
+        out.serialize_field("end_byte", &capture.end_byte)?;
+        out.end()
+    }
+}
+
+/// Where a pattern in an extractor's query came from.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct PatternOrigin {
+    pub(crate) query: usize,
+    pub(crate) query_label: Option<Arc<str>>,
+    pub(crate) query_file: Option<Arc<str>>,
+    pub(crate) rule: Option<Arc<Rule>>,
+}
+
+/// Whether rows and columns count from 0 (like tree-sitter and most editor
+/// protocols) or from 1 (like grep and most editors' UIs.) Byte offsets are
+/// always counted from 0, since they're offsets rather than positions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Coordinates {
+    ZeroBased,
+    OneBased,
+}
+
+impl Coordinates {
+    /// What to add to a 0-based row or column to get one in these
+    /// coordinates.
+    pub fn base(&self) -> usize {
+        match self {
+            Coordinates::ZeroBased => 0,
+            Coordinates::OneBased => 1,
+        }
+    }
+}
+
+impl FromStr for Coordinates {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "zero-based" | "0" => Ok(Coordinates::ZeroBased),
+            "one-based" | "1" => Ok(Coordinates::OneBased),
+            _ => bail!("unknown coordinates. Use zero-based or one-based."),
+        }
+    }
+}
+
+/// A place where a file didn't parse: either an `ERROR` node, covering
+/// source the parser couldn't make sense of, or a `MISSING` node, which the
+/// parser assumed was there so it could keep going.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ParseError {
+    missing: Option<&'static str>,
+    start: Point,
+    end: Point,
+    start_byte: usize,
+    end_byte: usize,
+    coordinates: Coordinates,
 }
 
-fn serialize_point<S>(point: &Point, sz: S) -> Result<S::Ok, S::Error>
-where
-    S: Serializer,
-{
-    let mut out = sz.serialize_struct("Point", 2)?;
-    out.serialize_field("row", &(point.row + 1))?;
-    out.serialize_field("column", &(point.column + 1))?;
-    out.end()
+impl ParseError {
+    /// The kind of node the parser assumed was there, for `MISSING` nodes.
+    pub fn missing(&self) -> Option<&'static str> {
+        self.missing
+    }
+
+    pub fn start(&self) -> Point {
+        self.start
+    }
+
+    pub fn end(&self) -> Point {
+        self.end
+    }
+
+    pub fn start_byte(&self) -> usize {
+        self.start_byte
+    }
+
+    pub fn end_byte(&self) -> usize {
+        self.end_byte
+    }
+
+    pub fn coordinates(&self) -> Coordinates {
+        self.coordinates
+    }
+}
+
+/// Displays like tree-sitter shows these nodes: `ERROR` or `MISSING ";"`.
+impl Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.missing {
+            Some(kind) => write!(f, "MISSING {:?}", kind),
+            None => write!(f, "ERROR"),
+        }
+    }
+}
+
+impl Serialize for ParseError {
+    fn serialize<S>(&self, sz: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut out = sz.serialize_struct("ParseError", 6)?;
+        out.serialize_field(
+            "kind",
+            if self.missing.is_some() {
+                "MISSING"
+            } else {
+                "ERROR"
+            },
+        )?;
+        if let Some(missing) = self.missing {
+            out.serialize_field("missing", missing)?;
+        }
+        out.serialize_field("start", &SerializePoint(self.start, self.coordinates))?;
+        out.serialize_field("end", &SerializePoint(self.end, self.coordinates))?;
+        out.serialize_field("start_byte", &self.start_byte)?;
+        out.serialize_field("end_byte", &self.end_byte)?;
+        out.end()
+    }
+}
+
+pub(crate) struct SerializePoint(pub(crate) Point, pub(crate) Coordinates);
+
+impl Serialize for SerializePoint {
+    fn serialize<S>(&self, sz: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let SerializePoint(point, coordinates) = self;
+
+        let mut out = sz.serialize_struct("Point", 2)?;
+        out.serialize_field("row", &(point.row + coordinates.base()))?;
+        out.serialize_field("column", &(point.column + coordinates.base()))?;
+        out.end()
+    }
 }
 
 #[cfg(test)]
+#[cfg(any(feature = "elm", feature = "javascript", feature = "tsx"))]
 mod tests {
     use super::*;
     use crate::language::Language;
     use tree_sitter::Parser;
 
     #[test]
+    #[cfg(feature = "elm")]
     fn test_matches_are_extracted() {
         let lang = Language::Elm;
         let query = lang
@@ -193,11 +1047,57 @@ mod tests {
             .unwrap();
 
         assert_eq!(extracted.matches.len(), 1);
-        assert_eq!(extracted.matches[0].name, "import");
+        assert_eq!(extracted.matches[0].name(), "import");
         assert_eq!(extracted.matches[0].text, "Html.Styled");
     }
 
     #[test]
+    #[cfg(feature = "elm")]
+    fn test_byte_offsets_cover_the_capture() {
+        let lang = Language::Elm;
+        let query = lang
+            .parse_query("(import_clause (upper_case_qid)@import)")
+            .unwrap();
+        let extractor = Extractor::new(lang, query);
+
+        let source = b"import Html.Styled";
+        let extracted = extractor
+            .extract_from_text(None, source, &mut Parser::new())
+            .unwrap()
+            .unwrap();
+
+        let extracted_match = &extracted.matches[0];
+        assert_eq!(extracted_match.start_byte(), 7);
+        assert_eq!(extracted_match.end_byte(), 18);
+        assert_eq!(
+            &source[extracted_match.start_byte()..extracted_match.end_byte()],
+            b"Html.Styled"
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "elm")]
+    fn test_zero_based_coordinates() {
+        let lang = Language::Elm;
+        let query = lang
+            .parse_query("(import_clause (upper_case_qid)@import)")
+            .unwrap();
+        let extractor = Extractor::new(lang, query).coordinates(Coordinates::ZeroBased);
+
+        let extracted = extractor
+            .extract_from_text(None, b"import Html.Styled", &mut Parser::new())
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(extracted.to_string(), "NO FILE:0:7:import:Html.Styled\n");
+        assert_eq!(
+            serde_json::to_value(&extracted.matches[0]).unwrap()["start"],
+            serde_json::json!({"row": 0, "column": 7}),
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "elm")]
     fn test_underscore_names_are_ignored() {
         let lang = Language::Elm;
         let query = lang
@@ -214,6 +1114,7 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "javascript")]
     fn test_underscore_names_can_still_be_used_in_matchers() {
         let lang = Language::JavaScript;
         let query = lang
@@ -229,7 +1130,182 @@ mod tests {
             .unwrap();
 
         assert_eq!(extracted.matches.len(), 1);
-        assert_eq!(extracted.matches[0].name, "import");
+        assert_eq!(extracted.matches[0].name(), "import");
         assert_eq!(extracted.matches[0].text, "\"foo.js\"");
     }
+
+    #[test]
+    #[cfg(feature = "javascript")]
+    fn test_grouped_matches_keep_captures_together() {
+        let lang = Language::JavaScript;
+        let query = lang
+            .parse_query(
+                "(call_expression function: (identifier)@fn arguments: (arguments (_)@arg))",
+            )
+            .unwrap();
+        let extractor = Extractor::new(lang, query);
+
+        let extracted = extractor
+            .extract_from_text(None, b"foo(1); bar(2)", &mut Parser::new())
+            .unwrap()
+            .unwrap();
+
+        let groups: Vec<Vec<&str>> = extracted
+            .grouped_matches()
+            .iter()
+            .map(|group| {
+                group
+                    .captures()
+                    .iter()
+                    .map(|capture| capture.text())
+                    .collect()
+            })
+            .collect();
+        assert_eq!(groups, vec![vec!["foo", "1"], vec!["bar", "2"]]);
+
+        let json = serde_json::to_value(&extracted.grouped_matches()[1]).unwrap();
+        assert_eq!(json["match_id"], 1);
+        assert_eq!(json["pattern"], 0);
+        assert_eq!(json["captures"]["fn"][0]["text"], "bar");
+        assert_eq!(json["captures"]["arg"][0]["text"], "2");
+    }
+
+    #[test]
+    #[cfg(feature = "javascript")]
+    fn test_javascript_understands_jsx() {
+        let lang = Language::JavaScript;
+        let query = lang
+            .parse_query("(jsx_element open_tag: (jsx_opening_element name: (_)@tag))")
+            .unwrap();
+        let extractor = Extractor::new(lang, query);
+
+        let extracted = extractor
+            .extract_from_text(
+                None,
+                b"const app = () => <App><Header title=\"hi\" /></App>;",
+                &mut Parser::new(),
+            )
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(extracted.matches.len(), 1);
+        assert_eq!(extracted.matches[0].text, "App");
+    }
+
+    #[test]
+    #[cfg(feature = "tsx")]
+    fn test_tsx_understands_types_and_jsx() {
+        let lang = Language::Tsx;
+        let query = lang
+            .parse_query("(jsx_self_closing_element name: (_)@tag)")
+            .unwrap();
+        let extractor = Extractor::new(lang, query);
+
+        let extracted = extractor
+            .extract_from_text(
+                None,
+                b"const header = (title: string): JSX.Element => <Header title={title} />;",
+                &mut Parser::new(),
+            )
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(extracted.matches.len(), 1);
+        assert_eq!(extracted.matches[0].text, "Header");
+    }
+
+    #[test]
+    #[cfg(feature = "javascript")]
+    fn test_parse_errors_are_reported() {
+        let lang = Language::JavaScript;
+        let query = lang.parse_query("(identifier)@id").unwrap();
+        let extractor = Extractor::new(lang, query).report_parse_errors(true);
+
+        let extracted = extractor
+            .extract_from_text(None, b"foo;\nfoo(;\n", &mut Parser::new())
+            .unwrap()
+            .unwrap();
+
+        assert!(extracted.has_errors());
+        assert_eq!(extracted.matches.len(), 2);
+        assert_eq!(extracted.parse_errors().len(), 1);
+        assert_eq!(extracted.parse_errors()[0].to_string(), "MISSING \")\"");
+        assert_eq!(extracted.parse_errors()[0].start().row, 1);
+        assert_eq!(extracted.parse_errors()[0].start().column, 4);
+    }
+
+    #[test]
+    #[cfg(feature = "javascript")]
+    fn test_files_with_errors_can_be_skipped() {
+        let lang = Language::JavaScript;
+        let query = lang.parse_query("(identifier)@id").unwrap();
+        let extractor = Extractor::new(lang, query).skip_files_with_errors(true);
+
+        let mut parser = Parser::new();
+        let skipped = extractor
+            .extract_from_text(None, b"foo;\nfoo(;\n", &mut parser)
+            .unwrap();
+        assert!(skipped.is_none());
+
+        let clean = extractor
+            .extract_from_text(None, b"foo;", &mut parser)
+            .unwrap()
+            .unwrap();
+        assert!(!clean.has_errors());
+        assert_eq!(clean.matches.len(), 1);
+    }
+
+    #[test]
+    #[cfg(feature = "javascript")]
+    fn test_big_files_are_skipped() {
+        let lang = Language::JavaScript;
+        let query = lang.parse_query("(identifier)@id").unwrap();
+        let extractor = Extractor::new(lang, query).max_filesize(Some(3));
+
+        let mut parser = Parser::new();
+        let err = extractor
+            .extract_from_text(None, b"foobar", &mut parser)
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<Skipped>(),
+            Some(&Skipped::TooBig {
+                size: 6,
+                max_filesize: 3
+            })
+        );
+
+        assert!(extractor
+            .extract_from_text(None, b"foo", &mut parser)
+            .unwrap()
+            .is_some());
+    }
+
+    #[test]
+    #[cfg(feature = "javascript")]
+    fn test_slow_files_are_skipped() {
+        let lang = Language::JavaScript;
+        let query = lang.parse_query("(identifier)@id").unwrap();
+        let timeout = Duration::from_nanos(1);
+        let extractor = Extractor::new(lang, query).parse_timeout(Some(timeout));
+
+        let mut parser = Parser::new();
+        let source = "foo(bar);\n".repeat(10_000);
+        let err = extractor
+            .extract_from_text(None, source.as_bytes(), &mut parser)
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<Skipped>(),
+            Some(&Skipped::Timeout(timeout))
+        );
+
+        // the parser shouldn't carry anything over from the file it gave up
+        // on.
+        let lang = Language::JavaScript;
+        let query = lang.parse_query("(identifier)@id").unwrap();
+        let extracted = Extractor::new(lang, query)
+            .extract_from_text(None, b"baz", &mut parser)
+            .unwrap()
+            .unwrap();
+        assert_eq!(extracted.matches[0].text, "baz");
+    }
 }
@@ -1,22 +1,184 @@
+use crate::detect;
 use crate::extractor::Extractor;
+use crate::language::Language;
 use anyhow::{bail, Context, Result};
 use ignore::types::{Types, TypesBuilder};
 use ignore::DirEntry;
 use std::collections::HashMap;
+use std::path::Path;
+
+/// Changes to which files each language is used for, on top of the usual
+/// ones (mostly ripgrep's file types, so `--type-list` in either tool looks
+/// about the same.) Clears happen before additions, so clearing `ruby` and
+/// adding `ruby:*.rb` means "only `.rb` files."
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FileTypes {
+    cleared: Vec<Language>,
+    added: Vec<(Language, String)>,
+}
+
+impl FileTypes {
+    pub fn new() -> FileTypes {
+        FileTypes::default()
+    }
+
+    /// Use `language` for files matching `glob`, like `*.mjs` or `Gemfile`.
+    /// If another language was going to be used for the same files (like C
+    /// for `*.h`) this one wins.
+    pub fn add(mut self, language: Language, glob: &str) -> FileTypes {
+        self.added.push((language, glob.to_string()));
+        self
+    }
+
+    /// Forget all the files `language` is usually used for.
+    pub fn clear(mut self, language: Language) -> FileTypes {
+        self.cleared.push(language);
+        self
+    }
+
+    fn adds_to(&self, language: &Language) -> bool {
+        self.added.iter().any(|(added, _)| added == language)
+    }
+
+    /// The globs for the files each of `languages` will be used for.
+    pub fn globs(&self, languages: &[Language]) -> Result<Vec<(Language, Vec<String>)>> {
+        let types_builder = self.types_builder(languages)?;
+        let definitions = types_builder.definitions();
+
+        Ok(languages
+            .iter()
+            .map(|language| {
+                let globs = definitions
+                    .iter()
+                    .find(|def| def.name() == language.name_for_types_builder())
+                    .map(|def| def.globs().to_vec())
+                    .unwrap_or_default();
+
+                (language.clone(), globs)
+            })
+            .collect())
+    }
+
+    fn types_builder<'language>(
+        &self,
+        languages: impl IntoIterator<Item = &'language Language>,
+    ) -> Result<TypesBuilder> {
+        let mut types_builder = TypesBuilder::new();
+        types_builder.add_defaults();
+
+        // the default `ts` type includes `.tsx` files, but they need the TSX
+        // grammar (plain TypeScript can't parse JSX.) So they get a type of
+        // their own.
+        types_builder.clear("ts");
+        for glob in ["*.ts", "*.cts", "*.mts"] {
+            types_builder
+                .add("ts", glob)
+                .context("could not set up the TypeScript file type")?;
+        }
+        types_builder
+            .add("tsx", "*.tsx")
+            .context("could not set up the TSX file type")?;
+
+        for language in languages {
+            let name = language.name_for_types_builder();
+
+            // grammars loaded at runtime say which files they're for. If they
+            // don't, we go with the file type of the same name (so a `kotlin`
+            // grammar gets `*.kt` and `*.kts`) or failing that `*.NAME`.
+            // (this is the only variant if no language features are on.)
+            #[allow(irrefutable_let_patterns)]
+            if let Language::Dynamic(grammar) = language {
+                let globs = if !grammar.globs().is_empty() {
+                    grammar.globs().to_vec()
+                } else if types_builder
+                    .definitions()
+                    .iter()
+                    .any(|def| def.name() == name)
+                {
+                    Vec::new()
+                } else {
+                    vec![format!("*.{}", name)]
+                };
+
+                if !globs.is_empty() {
+                    types_builder.clear(name);
+                }
+
+                for glob in globs {
+                    types_builder.add(name, &glob).with_context(|| {
+                        format!("could not use {} for the {} grammar", glob, name)
+                    })?;
+                }
+            }
+        }
+
+        for language in &self.cleared {
+            types_builder.clear(language.name_for_types_builder());
+        }
+
+        for (language, glob) in &self.added {
+            let name = language.name_for_types_builder();
+
+            // if another language has exactly the same glob, it'd be
+            // confusing to still see it there in --type-list. (Globs that
+            // only overlap are handled by selecting this language last; see
+            // `ExtractorChooser::from_extractors`.)
+            for def in types_builder.definitions() {
+                if def.name() != name && def.globs().contains(glob) {
+                    types_builder.clear(def.name());
+                    for other_glob in def.globs().iter().filter(|other| *other != glob) {
+                        types_builder.add(def.name(), other_glob).with_context(|| {
+                            format!("could not remove {} from {}", glob, def.name())
+                        })?;
+                    }
+                }
+            }
+
+            types_builder
+                .add(name, glob)
+                .with_context(|| format!("could not use {} for {}", glob, language))?;
+        }
+
+        Ok(types_builder)
+    }
+}
 
 pub struct ExtractorChooser<'extractor> {
     matcher: Types,
+    /// Every file type we know about, not just the ones for our extractors,
+    /// so we know which files not to bother detecting the language of.
+    known_types: Types,
     extractors: HashMap<&'extractor str, &'extractor Extractor>,
+    detect_language: bool,
 }
 
 impl<'extractor> ExtractorChooser<'extractor> {
-    pub fn from_extractors(extractors: &[Extractor]) -> Result<ExtractorChooser> {
-        let mut types_builder = TypesBuilder::new();
-        types_builder.add_defaults();
+    pub fn from_extractors(
+        extractors: &'extractor [Extractor],
+        file_types: &FileTypes,
+    ) -> Result<ExtractorChooser<'extractor>> {
+        let languages = || extractors.iter().map(|extractor| extractor.language());
+        let mut types_builder = file_types.types_builder(languages())?;
+
+        let mut known_types = file_types.types_builder(languages())?;
+        known_types.select("all");
 
         let mut names_to_extractors = HashMap::with_capacity(extractors.len());
 
-        for extractor in extractors {
+        // when globs from more than one language match a file, the language
+        // selected last wins. If someone told us which files a language is
+        // for, they want that to win over the usual globs for the others.
+        let (mut added, mut usual): (Vec<&Extractor>, Vec<&Extractor>) = extractors
+            .iter()
+            .partition(|extractor| file_types.adds_to(extractor.language()));
+
+        // otherwise, the order of the extractors (and so the queries, or a
+        // HashMap somewhere) shouldn't decide. Going through languages in
+        // reverse order means earlier ones win, like C over C++ for `*.h`.
+        usual.sort_by(|a, b| b.language().cmp(a.language()));
+        added.sort_by(|a, b| b.language().cmp(a.language()));
+
+        for extractor in usual.into_iter().chain(added) {
             let name = extractor.language().name_for_types_builder();
             types_builder.select(name);
 
@@ -31,13 +193,66 @@ impl<'extractor> ExtractorChooser<'extractor> {
             matcher: types_builder
                 .build()
                 .context("could not build a filetype matcher using provided extractors")?,
+            known_types: known_types
+                .build()
+                .context("could not build a filetype matcher for all file types")?,
             extractors: names_to_extractors,
+            detect_language: true,
         })
     }
 
-    pub fn extractor_for(&self, entry: &DirEntry) -> Option<&Extractor> {
+    /// Should we look inside files that none of our globs match for a
+    /// shebang or modeline that says what language they are? (Default: yes.)
+    pub fn detect_language(mut self, detect_language: bool) -> ExtractorChooser<'extractor> {
+        self.detect_language = detect_language;
+        self
+    }
+
+    pub fn extractor_for(&self, entry: &DirEntry) -> Option<&'extractor Extractor> {
         let is_dir = entry.file_type().map(|ft| ft.is_dir()).unwrap_or(true);
-        let matched = self.matcher.matched(entry.path(), is_dir);
+
+        self.extractor_for_path(entry.path(), is_dir)
+    }
+
+    /// Choose an extractor for a file on disk, by its name or (if that
+    /// doesn't tell us anything) its contents.
+    pub fn extractor_for_path(&self, path: &Path, is_dir: bool) -> Option<&'extractor Extractor> {
+        if let Some(extractor) = self.extractor_for_name(path, is_dir) {
+            return Some(extractor);
+        }
+
+        // if the name matched some other file type (like `.py` when we only
+        // have queries for Ruby) we already know we don't want it.
+        if !self.detect_language || is_dir || self.has_known_type(path) {
+            return None;
+        }
+
+        detect::language_for_file(path).and_then(|language| self.extractor_for_language(&language))
+    }
+
+    /// Choose an extractor for source we've already read, by its name (if
+    /// it has one) or its contents.
+    pub fn extractor_for_source(
+        &self,
+        path: Option<&Path>,
+        source: &[u8],
+    ) -> Option<&'extractor Extractor> {
+        if let Some(extractor) = path.and_then(|path| self.extractor_for_name(path, false)) {
+            return Some(extractor);
+        }
+
+        if !self.detect_language || matches!(path, Some(path) if self.has_known_type(path)) {
+            return None;
+        }
+
+        detect::language_for_source(source, &[])
+            .and_then(|language| self.extractor_for_language(&language))
+    }
+
+    /// Choose an extractor by name alone, for paths we haven't walked (or
+    /// which don't exist on disk at all.)
+    pub fn extractor_for_name(&self, path: &Path, is_dir: bool) -> Option<&'extractor Extractor> {
+        let matched = self.matcher.matched(path, is_dir);
 
         if !matched.is_whitelist() {
             return None;
@@ -49,4 +264,169 @@ impl<'extractor> ExtractorChooser<'extractor> {
             .and_then(|def| self.extractors.get(def.name()))
             .copied()
     }
+
+    fn extractor_for_language(&self, language: &Language) -> Option<&'extractor Extractor> {
+        self.extractors
+            .get(language.name_for_types_builder())
+            .copied()
+    }
+
+    fn has_known_type(&self, path: &Path) -> bool {
+        self.known_types.matched(path, false).is_whitelist()
+    }
+}
+
+#[cfg(test)]
+#[cfg(any(
+    all(feature = "c", feature = "cpp"),
+    feature = "javascript",
+    feature = "typescript"
+))]
+mod tests {
+    use super::*;
+
+    fn extractors(languages: &[Language]) -> Vec<Extractor> {
+        languages
+            .iter()
+            .map(|lang| Extractor::new(lang.clone(), lang.parse_query("(_)").unwrap()))
+            .collect()
+    }
+
+    fn language_for(extractors: &[Extractor], path: &str) -> Option<Language> {
+        language_with_types_for(extractors, &FileTypes::default(), path)
+    }
+
+    fn language_with_types_for(
+        extractors: &[Extractor],
+        file_types: &FileTypes,
+        path: &str,
+    ) -> Option<Language> {
+        ExtractorChooser::from_extractors(extractors, file_types)
+            .unwrap()
+            .extractor_for_path(Path::new(path), false)
+            .map(|extractor| extractor.language().clone())
+    }
+
+    #[test]
+    #[cfg(all(feature = "tsx", feature = "typescript"))]
+    fn tsx_files_get_the_tsx_grammar() {
+        let extractors = extractors(&[Language::TypeScript, Language::Tsx]);
+
+        assert_eq!(
+            language_for(&extractors, "a.ts"),
+            Some(Language::TypeScript)
+        );
+        assert_eq!(language_for(&extractors, "a.tsx"), Some(Language::Tsx));
+    }
+
+    #[test]
+    #[cfg(feature = "typescript")]
+    fn tsx_files_are_not_parsed_as_typescript() {
+        let extractors = extractors(&[Language::TypeScript]);
+
+        assert_eq!(language_for(&extractors, "a.tsx"), None);
+    }
+
+    #[test]
+    #[cfg(feature = "javascript")]
+    fn jsx_files_get_the_javascript_grammar() {
+        let extractors = extractors(&[Language::JavaScript]);
+
+        assert_eq!(
+            language_for(&extractors, "a.jsx"),
+            Some(Language::JavaScript)
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "javascript")]
+    fn added_globs_choose_the_language() {
+        let extractors = extractors(&[Language::JavaScript]);
+        let file_types = FileTypes::new().add(Language::JavaScript, "*.jsm");
+
+        assert_eq!(language_for(&extractors, "a.jsm"), None);
+        assert_eq!(
+            language_with_types_for(&extractors, &file_types, "a.jsm"),
+            Some(Language::JavaScript)
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "typescript")]
+    fn cleared_languages_only_get_added_globs() {
+        let extractors = extractors(&[Language::TypeScript]);
+        let file_types = FileTypes::new()
+            .clear(Language::TypeScript)
+            .add(Language::TypeScript, "*.mts");
+
+        assert_eq!(
+            language_with_types_for(&extractors, &file_types, "a.ts"),
+            None
+        );
+        assert_eq!(
+            language_with_types_for(&extractors, &file_types, "a.mts"),
+            Some(Language::TypeScript)
+        );
+    }
+
+    #[test]
+    #[cfg(all(feature = "javascript", feature = "typescript"))]
+    fn added_globs_move_from_other_languages() {
+        let extractors = extractors(&[Language::JavaScript, Language::TypeScript]);
+        let file_types = FileTypes::new().add(Language::JavaScript, "*.mts");
+
+        assert_eq!(
+            language_with_types_for(&extractors, &file_types, "a.mts"),
+            Some(Language::JavaScript)
+        );
+        assert_eq!(
+            language_with_types_for(&extractors, &file_types, "a.ts"),
+            Some(Language::TypeScript)
+        );
+    }
+
+    #[test]
+    #[cfg(all(feature = "c", feature = "cpp"))]
+    fn overlapping_globs_do_not_depend_on_extractor_order() {
+        assert_eq!(
+            language_for(&extractors(&[Language::C, Language::Cpp]), "a.h"),
+            Some(Language::C)
+        );
+        assert_eq!(
+            language_for(&extractors(&[Language::Cpp, Language::C]), "a.h"),
+            Some(Language::C)
+        );
+    }
+
+    #[test]
+    #[cfg(all(feature = "c", feature = "cpp"))]
+    fn added_globs_win_over_overlapping_ones() {
+        let extractors = extractors(&[Language::Cpp, Language::C]);
+        let file_types = FileTypes::new().add(Language::Cpp, "*.h");
+
+        assert_eq!(language_for(&extractors, "a.h"), Some(Language::C));
+        assert_eq!(
+            language_with_types_for(&extractors, &file_types, "a.h"),
+            Some(Language::Cpp)
+        );
+        assert_eq!(
+            language_with_types_for(&extractors, &file_types, "a.c"),
+            Some(Language::C)
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "typescript")]
+    fn globs_lists_the_effective_globs() {
+        let globs = FileTypes::new()
+            .clear(Language::TypeScript)
+            .add(Language::TypeScript, "*.mts")
+            .globs(&[Language::TypeScript])
+            .unwrap();
+
+        assert_eq!(
+            globs,
+            vec![(Language::TypeScript, vec!["*.mts".to_string()])]
+        );
+    }
 }
@@ -0,0 +1,342 @@
+use crate::extractor::{self, Extractor, Found};
+use crate::language::Language;
+use anyhow::{bail, Context, Result};
+use std::str::FromStr;
+use tree_sitter::{Parser, Query, QueryCursor, Range, Tree};
+
+/// How deep injections can nest (say, CSS in HTML in PHP.) This is only here
+/// so that a language injected into itself can't send us around in circles
+/// forever; real files don't get anywhere close.
+const MAX_DEPTH: usize = 4;
+
+/// The injections we know about out of the box, as host language, injected
+/// language, and query. They're only used when both languages are compiled
+/// in.
+const DEFAULTS: &[(&str, &str, &str)] = &[
+    (
+        "html",
+        "javascript",
+        r#"((script_element (raw_text) @injection.content) (#set! injection.language "javascript"))"#,
+    ),
+    (
+        "html",
+        "css",
+        r#"((style_element (raw_text) @injection.content) (#set! injection.language "css"))"#,
+    ),
+    (
+        "php",
+        "html",
+        r#"((text) @injection.content (#set! injection.language "html") (#set! injection.combined))"#,
+    ),
+];
+
+/// Code in one language embedded in another, like a `<script>` tag in HTML.
+/// The query (for the host language) uses the same conventions as Neovim's
+/// injection queries: `@injection.content` captures the embedded code, and
+/// the embedded language either comes from `#set! injection.language "name"`
+/// or from the text of an `@injection.language` capture. Matches with
+/// `#set! injection.combined` are parsed together as a single document
+/// instead of one document per match.
+#[derive(Debug)]
+pub struct Injection {
+    host: Language,
+    query: Query,
+    content: u32,
+    language: Option<u32>,
+    patterns: Vec<PatternSettings>,
+}
+
+#[derive(Debug)]
+struct PatternSettings {
+    language: Option<Language>,
+    combined: bool,
+}
+
+impl Injection {
+    pub fn new(host: Language, query: &str) -> Result<Injection> {
+        let query = host
+            .parse_query(query)
+            .with_context(|| format!("could not parse {} injection query", host))?;
+
+        let content = match query.capture_index_for_name("injection.content") {
+            Some(index) => index,
+            None => bail!("injection queries need an @injection.content capture"),
+        };
+        let language = query.capture_index_for_name("injection.language");
+
+        let mut patterns = Vec::with_capacity(query.pattern_count());
+        for pattern in 0..query.pattern_count() {
+            let mut settings = PatternSettings {
+                language: None,
+                combined: false,
+            };
+
+            for property in query.property_settings(pattern) {
+                match (&*property.key, property.value.as_deref()) {
+                    ("injection.language", Some(name)) => match language_for_name(name) {
+                        Some(injected) => settings.language = Some(injected),
+                        None => bail!("I don't know how to parse {} injected into {}", name, host),
+                    },
+                    ("injection.combined", _) => settings.combined = true,
+                    _ => (),
+                }
+            }
+
+            if settings.language.is_none() && language.is_none() {
+                bail!(
+                    "injection queries need to either capture @injection.language or set injection.language"
+                )
+            }
+
+            patterns.push(settings)
+        }
+
+        Ok(Injection {
+            host,
+            query,
+            content,
+            language,
+            patterns,
+        })
+    }
+
+    /// The injections we know about out of the box (`<script>` and `<style>`
+    /// tags in HTML, and the HTML around PHP) for whichever languages are
+    /// compiled in.
+    pub fn defaults() -> Result<Vec<Injection>> {
+        DEFAULTS
+            .iter()
+            .filter_map(|(host, injected, query)| {
+                let host = Language::from_str(host).ok()?;
+                Language::from_str(injected).ok()?;
+
+                Some(Injection::new(host, query))
+            })
+            .collect()
+    }
+
+    pub fn host(&self) -> &Language {
+        &self.host
+    }
+
+    /// The languages this injection can embed, or `None` if that depends on
+    /// the text of an `@injection.language` capture.
+    pub(crate) fn injected_languages(&self) -> Option<Vec<&Language>> {
+        self.patterns
+            .iter()
+            .map(|pattern| pattern.language.as_ref())
+            .collect()
+    }
+
+    /// Find the embedded regions in a host file. Each region is one document
+    /// in the injected language, made up of one or more ranges of the host.
+    fn regions(&self, tree: &Tree, source: &[u8]) -> Vec<(Language, Vec<Range>)> {
+        let mut cursor = QueryCursor::new();
+        let mut separate = Vec::new();
+        let mut combined: Vec<(usize, Language, Vec<Range>)> = Vec::new();
+
+        for query_match in cursor.matches(&self.query, tree.root_node(), source) {
+            let settings = &self.patterns[query_match.pattern_index];
+
+            let language = match &settings.language {
+                Some(language) => language.clone(),
+                None => match query_match
+                    .captures
+                    .iter()
+                    .filter(|capture| Some(capture.index) == self.language)
+                    .find_map(|capture| capture.node.utf8_text(source).ok())
+                    .and_then(language_for_name)
+                {
+                    Some(language) => language,
+                    // like a fenced code block in a language we don't know
+                    // about. Nothing we can do with it, so we leave it be.
+                    None => continue,
+                },
+            };
+
+            let ranges = query_match
+                .captures
+                .iter()
+                .filter(|capture| capture.index == self.content)
+                .map(|capture| capture.node.range())
+                .filter(|range| range.start_byte < range.end_byte);
+
+            if settings.combined {
+                match combined.iter_mut().find(|(pattern, other, _)| {
+                    *pattern == query_match.pattern_index && *other == language
+                }) {
+                    Some((_, _, existing)) => existing.extend(ranges),
+                    None => combined.push((query_match.pattern_index, language, ranges.collect())),
+                }
+            } else {
+                separate.push((language, ranges.collect()))
+            }
+        }
+
+        separate
+            .into_iter()
+            .chain(
+                combined
+                    .into_iter()
+                    .map(|(_, language, ranges)| (language, ranges)),
+            )
+            .filter(|(_, ranges): &(Language, Vec<Range>)| !ranges.is_empty())
+            .map(|(language, mut ranges)| {
+                // tree-sitter needs included ranges in order and without
+                // overlaps, which captures don't promise.
+                ranges.sort_by_key(|range| (range.start_byte, range.end_byte));
+                ranges.dedup_by(|next, prev| next.start_byte < prev.end_byte);
+                (language, ranges)
+            })
+            .collect()
+    }
+}
+
+/// Everything we need to look inside injected regions while extracting from
+/// a file: the injections, and the extractors to run on what they find.
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct Injector<'search> {
+    injections: &'search [Injection],
+    extractors: &'search [Extractor],
+}
+
+impl<'search> Injector<'search> {
+    pub(crate) fn new(
+        injections: &'search [Injection],
+        extractors: &'search [Extractor],
+    ) -> Injector<'search> {
+        Injector {
+            injections,
+            extractors,
+        }
+    }
+
+    /// Parse each region injected into `tree` and extract matches from it
+    /// into `found`. We parse the injected language over the whole host
+    /// source with only the region's ranges included, so nodes (and so
+    /// matches and edits) already have positions in the host file.
+    pub(crate) fn extract(
+        &self,
+        host: &Language,
+        tree: &Tree,
+        source: &[u8],
+        parser: &mut Parser,
+        found: &mut Found,
+    ) -> Result<()> {
+        self.extract_at_depth(host, tree, source, parser, found, 0)
+    }
+
+    fn extract_at_depth(
+        &self,
+        host: &Language,
+        tree: &Tree,
+        source: &[u8],
+        parser: &mut Parser,
+        found: &mut Found,
+        depth: usize,
+    ) -> Result<()> {
+        if depth >= MAX_DEPTH {
+            return Ok(());
+        }
+
+        for injection in self.injections.iter().filter(|i| &i.host == host) {
+            for (language, ranges) in injection.regions(tree, source) {
+                let extractor = self
+                    .extractors
+                    .iter()
+                    .find(|extractor| extractor.language() == &language);
+                let nests = self.injections.iter().any(|i| i.host == language);
+                if extractor.is_none() && !nests {
+                    continue;
+                }
+
+                let injected_tree = extractor::parse(
+                    language.language(),
+                    source,
+                    parser,
+                    &ranges,
+                    found.deadline(),
+                )
+                .with_context(|| format!("could not parse {} injected into {}", language, host))?;
+
+                found.note_errors(&injected_tree);
+                if let Some(extractor) = extractor {
+                    extractor.collect(&injected_tree, source, found)?;
+                }
+
+                self.extract_at_depth(&language, &injected_tree, source, parser, found, depth + 1)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Injection queries name languages the way editors do, which isn't always
+/// the way we do.
+fn language_for_name(name: &str) -> Option<Language> {
+    let name = name.trim().to_lowercase();
+    let name = match name.as_str() {
+        "sh" | "shell" | "zsh" => "bash",
+        "c++" | "cc" | "cxx" => "cpp",
+        "c#" | "cs" => "csharp",
+        "ex" | "exs" => "elixir",
+        "hs" => "haskell",
+        "js" | "jsx" | "node" => "javascript",
+        "py" | "python3" => "python",
+        "rb" => "ruby",
+        "rs" => "rust",
+        "ts" => "typescript",
+        "yml" => "yaml",
+        other => other,
+    };
+
+    Language::from_str(name).ok()
+}
+
+#[cfg(test)]
+#[cfg(feature = "html")]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn queries_need_content_and_a_language() {
+        assert!(Injection::new(Language::Html, "(raw_text) @code").is_err());
+        assert!(Injection::new(Language::Html, "(raw_text) @injection.content").is_err());
+        assert!(Injection::new(
+            Language::Html,
+            r#"((raw_text) @injection.content (#set! injection.language "cobol"))"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    #[cfg(all(feature = "html", feature = "javascript", feature = "css"))]
+    fn regions_come_from_the_host_tree() {
+        let source = "<p>hi</p>\n<script>let x = 1;</script>\n<style>p { color: red }</style>\n";
+        let mut parser = Parser::new();
+        let tree = extractor::parse(
+            Language::Html.language(),
+            source.as_bytes(),
+            &mut parser,
+            &[],
+            None,
+        )
+        .unwrap();
+
+        let regions: Vec<(Language, &str)> = Injection::defaults()
+            .unwrap()
+            .iter()
+            .flat_map(|injection| injection.regions(&tree, source.as_bytes()))
+            .map(|(language, ranges)| (language, &source[ranges[0].start_byte..ranges[0].end_byte]))
+            .collect();
+
+        assert_eq!(
+            regions,
+            vec![
+                (Language::JavaScript, "let x = 1;"),
+                (Language::Css, "p { color: red }"),
+            ]
+        );
+    }
+}
@@ -1,48 +1,211 @@
+use crate::dynamic_grammar::{self, DynamicGrammar};
 use anyhow::{anyhow, bail, Error, Result};
 use std::fmt::{Display, Formatter};
 use std::str::FromStr;
+use std::sync::Arc;
 
-#[derive(PartialEq, Eq, Hash, Debug)]
+/// A language we can search. The built-in languages each have a cargo
+/// feature (all on by default) so only the grammars you ask for get compiled
+/// in; `Dynamic` is for grammars loaded from shared libraries at runtime.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Clone)]
 pub enum Language {
+    #[cfg(feature = "bash")]
+    Bash,
+    #[cfg(feature = "c")]
+    C,
+    #[cfg(feature = "csharp")]
+    CSharp,
+    #[cfg(feature = "cpp")]
     Cpp,
+    #[cfg(feature = "css")]
+    Css,
+    #[cfg(feature = "elixir")]
     Elixir,
+    #[cfg(feature = "elm")]
     Elm,
+    #[cfg(feature = "go")]
+    Go,
+    #[cfg(feature = "haskell")]
     Haskell,
+    #[cfg(feature = "html")]
+    Html,
+    #[cfg(feature = "java")]
+    Java,
+    #[cfg(feature = "javascript")]
     JavaScript,
+    #[cfg(feature = "json")]
+    Json,
+    #[cfg(feature = "php")]
     Php,
+    #[cfg(feature = "python")]
+    Python,
+    #[cfg(feature = "ruby")]
     Ruby,
+    #[cfg(feature = "rust")]
     Rust,
+    #[cfg(feature = "tsx")]
+    Tsx,
+    #[cfg(feature = "typescript")]
     TypeScript,
+    #[cfg(feature = "yaml")]
+    Yaml,
+    Dynamic(Arc<DynamicGrammar>),
 }
 
 impl Language {
     pub fn all() -> Vec<Language> {
+        let mut all = Language::builtin();
+        all.extend(dynamic_grammar::all().into_iter().map(Language::Dynamic));
+        all
+    }
+
+    /// Make a grammar loaded at runtime available under its name (in
+    /// `FromStr`, `all`, and so on) for the rest of the process.
+    pub fn register(grammar: DynamicGrammar) -> Result<Language> {
+        if Language::builtin_from_str(grammar.name()).is_some() {
+            bail!(
+                "can't load a grammar called {} because there's already a built-in language with that name",
+                grammar.name()
+            )
+        }
+
+        dynamic_grammar::register(grammar).map(Language::Dynamic)
+    }
+
+    fn builtin() -> Vec<Language> {
         vec![
+            #[cfg(feature = "bash")]
+            Language::Bash,
+            #[cfg(feature = "c")]
+            Language::C,
+            #[cfg(feature = "csharp")]
+            Language::CSharp,
+            #[cfg(feature = "cpp")]
             Language::Cpp,
+            #[cfg(feature = "css")]
+            Language::Css,
+            #[cfg(feature = "elixir")]
             Language::Elixir,
+            #[cfg(feature = "elm")]
             Language::Elm,
+            #[cfg(feature = "go")]
+            Language::Go,
+            #[cfg(feature = "haskell")]
             Language::Haskell,
+            #[cfg(feature = "html")]
+            Language::Html,
+            #[cfg(feature = "java")]
+            Language::Java,
+            #[cfg(feature = "javascript")]
             Language::JavaScript,
+            #[cfg(feature = "json")]
+            Language::Json,
+            #[cfg(feature = "php")]
             Language::Php,
+            #[cfg(feature = "python")]
+            Language::Python,
+            #[cfg(feature = "ruby")]
             Language::Ruby,
+            #[cfg(feature = "rust")]
             Language::Rust,
+            #[cfg(feature = "tsx")]
+            Language::Tsx,
+            #[cfg(feature = "typescript")]
             Language::TypeScript,
+            #[cfg(feature = "yaml")]
+            Language::Yaml,
         ]
     }
 
+    fn builtin_from_str(s: &str) -> Option<Language> {
+        match s {
+            #[cfg(feature = "bash")]
+            "bash" => Some(Language::Bash),
+            #[cfg(feature = "c")]
+            "c" => Some(Language::C),
+            #[cfg(feature = "csharp")]
+            "csharp" => Some(Language::CSharp),
+            #[cfg(feature = "cpp")]
+            "cpp" => Some(Language::Cpp),
+            #[cfg(feature = "css")]
+            "css" => Some(Language::Css),
+            #[cfg(feature = "elixir")]
+            "elixir" => Some(Language::Elixir),
+            #[cfg(feature = "elm")]
+            "elm" => Some(Language::Elm),
+            #[cfg(feature = "go")]
+            "go" => Some(Language::Go),
+            #[cfg(feature = "haskell")]
+            "haskell" => Some(Language::Haskell),
+            #[cfg(feature = "html")]
+            "html" => Some(Language::Html),
+            #[cfg(feature = "java")]
+            "java" => Some(Language::Java),
+            #[cfg(feature = "javascript")]
+            "javascript" => Some(Language::JavaScript),
+            #[cfg(feature = "json")]
+            "json" => Some(Language::Json),
+            #[cfg(feature = "php")]
+            "php" => Some(Language::Php),
+            #[cfg(feature = "python")]
+            "python" => Some(Language::Python),
+            #[cfg(feature = "ruby")]
+            "ruby" => Some(Language::Ruby),
+            #[cfg(feature = "rust")]
+            "rust" => Some(Language::Rust),
+            #[cfg(feature = "tsx")]
+            "tsx" => Some(Language::Tsx),
+            #[cfg(feature = "typescript")]
+            "typescript" => Some(Language::TypeScript),
+            #[cfg(feature = "yaml")]
+            "yaml" => Some(Language::Yaml),
+            _ => None,
+        }
+    }
+
     pub fn language(&self) -> tree_sitter::Language {
-        unsafe {
-            match self {
-                Language::Cpp => tree_sitter_cpp(),
-                Language::Elixir => tree_sitter_elixir(),
-                Language::Elm => tree_sitter_elm(),
-                Language::Haskell => tree_sitter_haskell(),
-                Language::JavaScript => tree_sitter_javascript(),
-                Language::Php => tree_sitter_php(),
-                Language::Ruby => tree_sitter_ruby(),
-                Language::Rust => tree_sitter_rust(),
-                Language::TypeScript => tree_sitter_typescript(),
-            }
+        match self {
+            #[cfg(feature = "bash")]
+            Language::Bash => unsafe { tree_sitter_bash() },
+            #[cfg(feature = "c")]
+            Language::C => unsafe { tree_sitter_c() },
+            #[cfg(feature = "csharp")]
+            Language::CSharp => unsafe { tree_sitter_c_sharp() },
+            #[cfg(feature = "cpp")]
+            Language::Cpp => unsafe { tree_sitter_cpp() },
+            #[cfg(feature = "css")]
+            Language::Css => unsafe { tree_sitter_css() },
+            #[cfg(feature = "elixir")]
+            Language::Elixir => unsafe { tree_sitter_elixir() },
+            #[cfg(feature = "elm")]
+            Language::Elm => unsafe { tree_sitter_elm() },
+            #[cfg(feature = "go")]
+            Language::Go => unsafe { tree_sitter_go() },
+            #[cfg(feature = "haskell")]
+            Language::Haskell => unsafe { tree_sitter_haskell() },
+            #[cfg(feature = "html")]
+            Language::Html => unsafe { tree_sitter_html() },
+            #[cfg(feature = "java")]
+            Language::Java => unsafe { tree_sitter_java() },
+            #[cfg(feature = "javascript")]
+            Language::JavaScript => unsafe { tree_sitter_javascript() },
+            #[cfg(feature = "json")]
+            Language::Json => unsafe { tree_sitter_json() },
+            #[cfg(feature = "php")]
+            Language::Php => unsafe { tree_sitter_php() },
+            #[cfg(feature = "python")]
+            Language::Python => unsafe { tree_sitter_python() },
+            #[cfg(feature = "ruby")]
+            Language::Ruby => unsafe { tree_sitter_ruby() },
+            #[cfg(feature = "rust")]
+            Language::Rust => unsafe { tree_sitter_rust() },
+            #[cfg(feature = "tsx")]
+            Language::Tsx => unsafe { tree_sitter_tsx() },
+            #[cfg(feature = "typescript")]
+            Language::TypeScript => unsafe { tree_sitter_typescript() },
+            #[cfg(feature = "yaml")]
+            Language::Yaml => unsafe { tree_sitter_yaml() },
+            Language::Dynamic(grammar) => grammar.language(),
         }
     }
 
@@ -52,15 +215,47 @@ impl Language {
 
     pub fn name_for_types_builder(&self) -> &str {
         match self {
+            #[cfg(feature = "bash")]
+            Language::Bash => "sh",
+            #[cfg(feature = "c")]
+            Language::C => "c",
+            #[cfg(feature = "csharp")]
+            Language::CSharp => "csharp",
+            #[cfg(feature = "cpp")]
             Language::Cpp => "cpp",
+            #[cfg(feature = "css")]
+            Language::Css => "css",
+            #[cfg(feature = "elixir")]
             Language::Elixir => "elixir",
+            #[cfg(feature = "elm")]
             Language::Elm => "elm",
+            #[cfg(feature = "go")]
+            Language::Go => "go",
+            #[cfg(feature = "haskell")]
             Language::Haskell => "haskell",
+            #[cfg(feature = "html")]
+            Language::Html => "html",
+            #[cfg(feature = "java")]
+            Language::Java => "java",
+            #[cfg(feature = "javascript")]
             Language::JavaScript => "js",
+            #[cfg(feature = "json")]
+            Language::Json => "json",
+            #[cfg(feature = "php")]
             Language::Php => "php",
+            #[cfg(feature = "python")]
+            Language::Python => "py",
+            #[cfg(feature = "ruby")]
             Language::Ruby => "ruby",
+            #[cfg(feature = "rust")]
             Language::Rust => "rust",
+            #[cfg(feature = "tsx")]
+            Language::Tsx => "tsx",
+            #[cfg(feature = "typescript")]
             Language::TypeScript => "ts",
+            #[cfg(feature = "yaml")]
+            Language::Yaml => "yaml",
+            Language::Dynamic(grammar) => grammar.name(),
         }
     }
 }
@@ -69,17 +264,13 @@ impl FromStr for Language {
     type Err = Error;
 
     fn from_str(s: &str) -> Result<Self> {
-        match s {
-            "cpp" => Ok(Language::Cpp),
-            "elixir" => Ok(Language::Elixir),
-            "elm" => Ok(Language::Elm),
-            "haskell" => Ok(Language::Haskell),
-            "javascript" => Ok(Language::JavaScript),
-            "php" => Ok(Language::Php),
-            "ruby" => Ok(Language::Ruby),
-            "rust" => Ok(Language::Rust),
-            "typescript" => Ok(Language::TypeScript),
-            _ => bail!(
+        if let Some(language) = Language::builtin_from_str(s) {
+            return Ok(language);
+        }
+
+        match dynamic_grammar::find(s) {
+            Some(grammar) => Ok(Language::Dynamic(grammar)),
+            None => bail!(
                 "unknown language {}. Try one of: {}",
                 s,
                 Language::all()
@@ -95,19 +286,94 @@ impl FromStr for Language {
 impl Display for Language {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
         match self {
+            #[cfg(feature = "bash")]
+            Language::Bash => f.write_str("bash"),
+            #[cfg(feature = "c")]
+            Language::C => f.write_str("c"),
+            #[cfg(feature = "csharp")]
+            Language::CSharp => f.write_str("csharp"),
+            #[cfg(feature = "cpp")]
             Language::Cpp => f.write_str("cpp"),
+            #[cfg(feature = "css")]
+            Language::Css => f.write_str("css"),
+            #[cfg(feature = "elixir")]
             Language::Elixir => f.write_str("elixir"),
+            #[cfg(feature = "elm")]
             Language::Elm => f.write_str("elm"),
+            #[cfg(feature = "go")]
+            Language::Go => f.write_str("go"),
+            #[cfg(feature = "haskell")]
             Language::Haskell => f.write_str("haskell"),
+            #[cfg(feature = "html")]
+            Language::Html => f.write_str("html"),
+            #[cfg(feature = "java")]
+            Language::Java => f.write_str("java"),
+            #[cfg(feature = "javascript")]
             Language::JavaScript => f.write_str("javascript"),
+            #[cfg(feature = "json")]
+            Language::Json => f.write_str("json"),
+            #[cfg(feature = "php")]
             Language::Php => f.write_str("php"),
+            #[cfg(feature = "python")]
+            Language::Python => f.write_str("python"),
+            #[cfg(feature = "ruby")]
             Language::Ruby => f.write_str("ruby"),
+            #[cfg(feature = "rust")]
             Language::Rust => f.write_str("rust"),
+            #[cfg(feature = "tsx")]
+            Language::Tsx => f.write_str("tsx"),
+            #[cfg(feature = "typescript")]
             Language::TypeScript => f.write_str("typescript"),
+            #[cfg(feature = "yaml")]
+            Language::Yaml => f.write_str("yaml"),
+            Language::Dynamic(grammar) => f.write_str(grammar.name()),
         }
     }
 }
 
+extern "C" {
+    #[cfg(feature = "bash")]
+    fn tree_sitter_bash() -> tree_sitter::Language;
+    #[cfg(feature = "c")]
+    fn tree_sitter_c() -> tree_sitter::Language;
+    #[cfg(feature = "csharp")]
+    fn tree_sitter_c_sharp() -> tree_sitter::Language;
+    #[cfg(feature = "cpp")]
+    fn tree_sitter_cpp() -> tree_sitter::Language;
+    #[cfg(feature = "css")]
+    fn tree_sitter_css() -> tree_sitter::Language;
+    #[cfg(feature = "elixir")]
+    fn tree_sitter_elixir() -> tree_sitter::Language;
+    #[cfg(feature = "elm")]
+    fn tree_sitter_elm() -> tree_sitter::Language;
+    #[cfg(feature = "go")]
+    fn tree_sitter_go() -> tree_sitter::Language;
+    #[cfg(feature = "haskell")]
+    fn tree_sitter_haskell() -> tree_sitter::Language;
+    #[cfg(feature = "html")]
+    fn tree_sitter_html() -> tree_sitter::Language;
+    #[cfg(feature = "java")]
+    fn tree_sitter_java() -> tree_sitter::Language;
+    #[cfg(feature = "javascript")]
+    fn tree_sitter_javascript() -> tree_sitter::Language;
+    #[cfg(feature = "json")]
+    fn tree_sitter_json() -> tree_sitter::Language;
+    #[cfg(feature = "php")]
+    fn tree_sitter_php() -> tree_sitter::Language;
+    #[cfg(feature = "python")]
+    fn tree_sitter_python() -> tree_sitter::Language;
+    #[cfg(feature = "ruby")]
+    fn tree_sitter_ruby() -> tree_sitter::Language;
+    #[cfg(feature = "rust")]
+    fn tree_sitter_rust() -> tree_sitter::Language;
+    #[cfg(feature = "tsx")]
+    fn tree_sitter_tsx() -> tree_sitter::Language;
+    #[cfg(feature = "typescript")]
+    fn tree_sitter_typescript() -> tree_sitter::Language;
+    #[cfg(feature = "yaml")]
+    fn tree_sitter_yaml() -> tree_sitter::Language;
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -123,11 +389,13 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "elm")]
     fn parse_query_smoke_test() {
         assert!(Language::Elm.parse_query("(_)").is_ok());
     }
 
     #[test]
+    #[cfg(feature = "elm")]
     fn parse_query_problem() {
         // tree-grepper 1.0 just printed the error struct when problems like
         // this happened. This test is just here to make sure we take a slightly
@@ -141,15 +409,3 @@ mod tests {
         )
     }
 }
-
-extern "C" {
-    fn tree_sitter_cpp() -> tree_sitter::Language;
-    fn tree_sitter_elixir() -> tree_sitter::Language;
-    fn tree_sitter_elm() -> tree_sitter::Language;
-    fn tree_sitter_haskell() -> tree_sitter::Language;
-    fn tree_sitter_javascript() -> tree_sitter::Language;
-    fn tree_sitter_php() -> tree_sitter::Language;
-    fn tree_sitter_ruby() -> tree_sitter::Language;
-    fn tree_sitter_rust() -> tree_sitter::Language;
-    fn tree_sitter_typescript() -> tree_sitter::Language;
-}
@@ -0,0 +1,47 @@
+//! tree-grepper works like `grep`, but uses tree-sitter to search for
+//! structure instead of strings. This is the library half: the `tree-grepper`
+//! binary is a thin command-line interface over what's in here.
+//!
+//! ```no_run
+//! use tree_grepper::{Language, Search};
+//!
+//! # fn main() -> anyhow::Result<()> {
+//! // languages are cargo features, so looking them up by name works no
+//! // matter which ones are compiled in (`Language::Elm` needs `elm`.)
+//! let elm: Language = "elm".parse()?;
+//!
+//! let search = Search::builder()
+//!     .query(elm, "(import_clause (upper_case_qid)@name)")
+//!     .path("src")
+//!     .build()?;
+//!
+//! for extracted_file in search.run()? {
+//!     for extracted_match in extracted_file?.matches() {
+//!         println!("{}", extracted_match.text());
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+mod detect;
+mod dynamic_grammar;
+mod extractor;
+mod extractor_chooser;
+mod injection;
+mod language;
+mod query_file;
+mod rewrite;
+mod rules;
+mod search;
+
+pub use dynamic_grammar::DynamicGrammar;
+pub use extractor::{
+    Coordinates, ExtractedFile, ExtractedMatch, Extractor, GroupedMatch, ParseError, Skipped,
+};
+pub use extractor_chooser::{ExtractorChooser, FileTypes};
+pub use injection::Injection;
+pub use language::Language;
+pub use rewrite::{Edit, Rewrite};
+pub use rules::{Rule, Severity};
+pub use search::{FileError, Results, Search, SearchBuilder};
@@ -0,0 +1,616 @@
+use anyhow::{bail, Error, Result};
+use std::borrow::Cow;
+use std::env;
+use std::io;
+use std::str::FromStr;
+use termcolor::{Color, ColorSpec, WriteColor};
+use tree_grepper::{ExtractedFile, ExtractedMatch, Severity};
+
+/// What to do with capture text that spans multiple lines in line-oriented
+/// output.
+#[derive(Debug)]
+pub enum Multiline {
+    /// Print the text as-is, newlines and all.
+    Keep,
+    /// Print `\n` and `\r` as escape sequences (and `\` as `\\`, so it can
+    /// be reversed.)
+    Escape,
+    /// Print only the first line, followed by `...` if there was more.
+    Truncate,
+}
+
+impl Multiline {
+    fn apply<'text>(&self, text: &'text str) -> Cow<'text, str> {
+        match self {
+            Multiline::Keep => Cow::Borrowed(text),
+
+            Multiline::Escape => {
+                if !text.contains(&['\\', '\n', '\r'][..]) {
+                    return Cow::Borrowed(text);
+                }
+
+                let mut escaped = String::with_capacity(text.len());
+                for c in text.chars() {
+                    match c {
+                        '\\' => escaped.push_str("\\\\"),
+                        '\n' => escaped.push_str("\\n"),
+                        '\r' => escaped.push_str("\\r"),
+                        _ => escaped.push(c),
+                    }
+                }
+
+                Cow::Owned(escaped)
+            }
+
+            Multiline::Truncate => match text.find('\n') {
+                Some(newline) => {
+                    Cow::Owned(format!("{}...", text[..newline].trim_end_matches('\r')))
+                }
+                None => Cow::Borrowed(text),
+            },
+        }
+    }
+}
+
+impl FromStr for Multiline {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "keep" => Ok(Multiline::Keep),
+            "escape" => Ok(Multiline::Escape),
+            "truncate" => Ok(Multiline::Truncate),
+            _ => bail!("unknown multiline mode. See --help for valid modes."),
+        }
+    }
+}
+
+/// When to color line-oriented output.
+#[derive(Debug)]
+pub enum Coloring {
+    Always,
+    Never,
+    /// Color only when we're printing to a terminal, unless `NO_COLOR` is set
+    /// or the terminal says it can't handle it.
+    Auto,
+}
+
+impl Coloring {
+    pub fn enabled(&self, terminal: bool) -> bool {
+        match self {
+            Coloring::Always => true,
+            Coloring::Never => false,
+            Coloring::Auto => {
+                terminal
+                    && env::var_os("NO_COLOR").is_none()
+                    && env::var_os("TERM") != Some("dumb".into())
+            }
+        }
+    }
+}
+
+impl FromStr for Coloring {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "always" => Ok(Coloring::Always),
+            "never" => Ok(Coloring::Never),
+            "auto" => Ok(Coloring::Auto),
+            _ => bail!("unknown color setting. See --help for valid settings."),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct LinesOpts {
+    pub before: usize,
+    pub after: usize,
+    pub full_lines: bool,
+    pub multiline: Multiline,
+    pub color: Coloring,
+    /// Print where each match ends as well as where it starts.
+    pub ranges: bool,
+    /// Print the byte offset of each match (or the byte range, with
+    /// `ranges`.)
+    pub byte_offset: bool,
+    /// Print which query each match came from (its label, or its index.)
+    pub show_query: bool,
+}
+
+impl LinesOpts {
+    /// Showing source lines around matches means we have to keep the source
+    /// of each file around until we print it.
+    pub fn needs_source(&self) -> bool {
+        self.before > 0 || self.after > 0 || self.full_lines
+    }
+}
+
+/// Prints extracted files one match per line, like `file:row:column:name:text`
+/// (or `file:row:column-row:column:name:text` with `ranges`, with a byte
+/// offset or range after the position with `byte_offset`, and with the query
+/// before the name with `show_query`.) Matches from rules print like compiler
+/// diagnostics instead: `file:row:column: severity: message [rule]`.
+/// When asked for context, each match is followed by a block of source lines
+/// marked like grep does: `file:row:line` for lines the capture spans and
+/// `file-row-line` for the lines around it, with `--` between blocks. Also
+/// like grep, matches whose blocks would overlap (or touch) share one block,
+/// so each source line is only printed once.
+///
+/// Colors work like rg's: file names are magenta, line and column numbers are
+/// green, and capture names are cyan. In source lines, the captured part is
+/// highlighted in bold red. Whether any of this actually shows up depends on
+/// the `WriteColor` we're given.
+pub struct LinesPrinter {
+    opts: LinesOpts,
+    printed_block: bool,
+    path_color: ColorSpec,
+    position_color: ColorSpec,
+    name_color: ColorSpec,
+    match_color: ColorSpec,
+    error_color: ColorSpec,
+    warning_color: ColorSpec,
+    info_color: ColorSpec,
+}
+
+impl LinesPrinter {
+    pub fn new(opts: LinesOpts) -> LinesPrinter {
+        let mut path_color = ColorSpec::new();
+        path_color.set_fg(Some(Color::Magenta));
+
+        let mut position_color = ColorSpec::new();
+        position_color.set_fg(Some(Color::Green));
+
+        let mut name_color = ColorSpec::new();
+        name_color.set_fg(Some(Color::Cyan));
+
+        let mut match_color = ColorSpec::new();
+        match_color.set_fg(Some(Color::Red)).set_bold(true);
+
+        let mut error_color = ColorSpec::new();
+        error_color.set_fg(Some(Color::Red)).set_bold(true);
+
+        let mut warning_color = ColorSpec::new();
+        warning_color.set_fg(Some(Color::Yellow)).set_bold(true);
+
+        let mut info_color = ColorSpec::new();
+        info_color.set_fg(Some(Color::Blue)).set_bold(true);
+
+        LinesPrinter {
+            opts,
+            printed_block: false,
+            path_color,
+            position_color,
+            name_color,
+            match_color,
+            error_color,
+            warning_color,
+            info_color,
+        }
+    }
+
+    fn severity_color(&self, severity: Severity) -> &ColorSpec {
+        match severity {
+            Severity::Error => &self.error_color,
+            Severity::Warning => &self.warning_color,
+            Severity::Info => &self.info_color,
+        }
+    }
+
+    pub fn write(
+        &mut self,
+        out: &mut impl WriteColor,
+        extracted_file: &ExtractedFile,
+    ) -> io::Result<()> {
+        let filename = extracted_file.display_name();
+
+        let source_lines: Option<Vec<&[u8]>> = match extracted_file.source() {
+            Some(source) if self.opts.needs_source() => {
+                let mut lines: Vec<&[u8]> = source.split(|byte| *byte == b'\n').collect();
+
+                // a trailing newline ends the last line instead of starting a
+                // new (empty) one.
+                if source.ends_with(b"\n") {
+                    lines.pop();
+                }
+
+                Some(lines)
+            }
+            _ => None,
+        };
+
+        let lines = match &source_lines {
+            Some(lines) => lines,
+            None => {
+                for extraction in extracted_file.matches() {
+                    self.write_match(out, filename, extraction)?;
+                }
+                return Ok(());
+            }
+        };
+
+        for block in context_blocks(extracted_file.matches(), &self.opts, lines.len()) {
+            if self.printed_block {
+                writeln!(out, "--")?;
+            }
+            self.printed_block = true;
+
+            for extraction in &block.matches {
+                self.write_match(out, filename, extraction)?;
+            }
+
+            let base = block.matches[0].coordinates().base();
+            for (row, line) in lines
+                .iter()
+                .enumerate()
+                .take(block.last + 1)
+                .skip(block.first)
+            {
+                let line = line.strip_suffix(b"\r").unwrap_or(line);
+
+                let mut highlights: Vec<(usize, usize)> = block
+                    .matches
+                    .iter()
+                    .filter(|extraction| {
+                        let (first, last) = spanned_rows(extraction);
+                        (first..=last).contains(&row)
+                    })
+                    .map(|extraction| highlighted_range(extraction, row, line.len()))
+                    .collect();
+                highlights.sort_unstable();
+
+                let separator = if highlights.is_empty() { '-' } else { ':' };
+
+                write_colored(out, &self.path_color, filename)?;
+                write!(out, "{}", separator)?;
+                write_colored(out, &self.position_color, row + base)?;
+                write!(out, "{}", separator)?;
+
+                let mut printed = 0;
+                for (start, end) in highlights {
+                    // matches on the same line can overlap (say, a call and
+                    // its arguments), so we only highlight what's left.
+                    if start < printed && end <= printed {
+                        continue;
+                    }
+                    let start = start.max(printed);
+                    let end = end.max(start);
+
+                    write!(out, "{}", String::from_utf8_lossy(&line[printed..start]))?;
+                    write_colored(
+                        out,
+                        &self.match_color,
+                        String::from_utf8_lossy(&line[start..end]),
+                    )?;
+                    printed = end;
+                }
+                writeln!(out, "{}", String::from_utf8_lossy(&line[printed..]))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Print the line for a single match, like `file:row:column:name:text`.
+    fn write_match(
+        &self,
+        out: &mut impl WriteColor,
+        filename: &str,
+        extraction: &ExtractedMatch,
+    ) -> io::Result<()> {
+        write_colored(out, &self.path_color, filename)?;
+        write!(out, ":")?;
+        let base = extraction.coordinates().base();
+
+        write_colored(out, &self.position_color, extraction.start().row + base)?;
+        write!(out, ":")?;
+        write_colored(out, &self.position_color, extraction.start().column + base)?;
+        if self.opts.ranges {
+            write!(out, "-")?;
+            write_colored(out, &self.position_color, extraction.end().row + base)?;
+            write!(out, ":")?;
+            write_colored(out, &self.position_color, extraction.end().column + base)?;
+        }
+        write!(out, ":")?;
+        if self.opts.byte_offset {
+            write_colored(out, &self.position_color, extraction.start_byte())?;
+            if self.opts.ranges {
+                write!(out, "-")?;
+                write_colored(out, &self.position_color, extraction.end_byte())?;
+            }
+            write!(out, ":")?;
+        }
+        match extraction.rule() {
+            // matches from rules look like compiler diagnostics, so
+            // editors and CI systems can pick them up.
+            Some(rule) => {
+                write!(out, " ")?;
+                write_colored(out, self.severity_color(rule.severity()), rule.severity())?;
+                write!(out, ": {} [", rule.message())?;
+                write_colored(out, &self.name_color, rule.id())?;
+                write!(out, "]")?;
+            }
+            None => {
+                if self.opts.show_query {
+                    match extraction.query_label() {
+                        Some(label) => write_colored(out, &self.name_color, label)?,
+                        None => write_colored(out, &self.name_color, extraction.query())?,
+                    }
+                    write!(out, ":")?;
+                }
+                write_colored(out, &self.name_color, extraction.name())?;
+                write!(out, ":")?;
+                write_colored(
+                    out,
+                    &self.match_color,
+                    self.opts.multiline.apply(extraction.text()),
+                )?;
+            }
+        }
+        writeln!(out)
+    }
+
+    /// Print matches grouped by query match: a header line with where the
+    /// match starts, its id and its pattern, followed by an indented line for
+    /// each capture, like `  @name row:column: text`.
+    pub fn write_grouped(
+        &mut self,
+        out: &mut impl WriteColor,
+        extracted_file: &ExtractedFile,
+    ) -> io::Result<()> {
+        let filename = extracted_file.display_name();
+
+        for group in extracted_file.grouped_matches() {
+            let first = group.first();
+            let base = first.coordinates().base();
+
+            write_colored(out, &self.path_color, filename)?;
+            write!(out, ":")?;
+            write_colored(out, &self.position_color, first.start().row + base)?;
+            write!(out, ":")?;
+            write_colored(out, &self.position_color, first.start().column + base)?;
+            writeln!(
+                out,
+                ": match {} (pattern {})",
+                first.match_id(),
+                first.pattern()
+            )?;
+
+            for capture in group.captures() {
+                write!(out, "  ")?;
+                write_colored(out, &self.name_color, format_args!("@{}", capture.name()))?;
+                write!(out, " ")?;
+                write_colored(out, &self.position_color, capture.start().row + base)?;
+                write!(out, ":")?;
+                write_colored(out, &self.position_color, capture.start().column + base)?;
+                write!(out, ": ")?;
+                write_colored(
+                    out,
+                    &self.match_color,
+                    self.opts.multiline.apply(capture.text()),
+                )?;
+                writeln!(out)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Source lines to print together, and the matches they're for.
+struct ContextBlock<'file> {
+    first: usize,
+    last: usize,
+    matches: Vec<&'file ExtractedMatch>,
+}
+
+/// Group matches whose context would overlap or touch, so we can print each
+/// group's lines once.
+fn context_blocks<'file>(
+    matches: &'file [ExtractedMatch],
+    opts: &LinesOpts,
+    line_count: usize,
+) -> Vec<ContextBlock<'file>> {
+    // matches from injected languages come after the host's matches, so we
+    // have to put them back in source order before grouping.
+    let mut sorted: Vec<&ExtractedMatch> = matches.iter().collect();
+    sorted.sort_by_key(|extraction| (extraction.start_byte(), extraction.end_byte()));
+
+    let mut blocks: Vec<ContextBlock> = Vec::new();
+
+    for extraction in sorted {
+        let (first_spanned, last_spanned) = spanned_rows(extraction);
+        let first = first_spanned.saturating_sub(opts.before);
+        let last = (last_spanned + opts.after).min(line_count.saturating_sub(1));
+
+        match blocks.last_mut() {
+            Some(block) if first <= block.last + 1 && last + 1 >= block.first => {
+                block.first = block.first.min(first);
+                block.last = block.last.max(last);
+                block.matches.push(extraction);
+            }
+            _ => blocks.push(ContextBlock {
+                first,
+                last,
+                matches: vec![extraction],
+            }),
+        }
+    }
+
+    blocks
+}
+
+/// The rows a match covers, for marking them with `:` instead of `-`.
+fn spanned_rows(extraction: &ExtractedMatch) -> (usize, usize) {
+    let first = extraction.start().row;
+
+    // a capture that ends right after a newline (like a whole item including
+    // its trailing newline) doesn't really span the line after it.
+    let last = if extraction.end().column == 0 && extraction.end().row > first {
+        extraction.end().row - 1
+    } else {
+        extraction.end().row
+    };
+
+    (first, last)
+}
+
+/// Which bytes of a source line the match covers. Tree-sitter columns are
+/// byte offsets into the row, so this is just the capture's start and end
+/// clamped to the row we're printing.
+fn highlighted_range(extraction: &ExtractedMatch, row: usize, line_len: usize) -> (usize, usize) {
+    let start = if row == extraction.start().row {
+        extraction.start().column.min(line_len)
+    } else {
+        0
+    };
+
+    let end = if row == extraction.end().row {
+        extraction.end().column.clamp(start, line_len)
+    } else {
+        line_len
+    };
+
+    (start, end)
+}
+
+fn write_colored(
+    out: &mut impl WriteColor,
+    color: &ColorSpec,
+    item: impl std::fmt::Display,
+) -> io::Result<()> {
+    out.set_color(color)?;
+    write!(out, "{}", item)?;
+    out.reset()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "javascript")]
+    fn lines_with_context(before: usize, after: usize, source: &str) -> String {
+        use std::path::Path;
+        use tree_grepper::{Extractor, Language};
+
+        let lang = Language::JavaScript;
+        let query = lang.parse_query("(number)@n").unwrap();
+        let extracted = Extractor::new(lang, query)
+            .keep_source(true)
+            .extract_from_text(
+                Some(Path::new("a.js")),
+                source.as_bytes(),
+                &mut tree_sitter::Parser::new(),
+            )
+            .unwrap()
+            .unwrap();
+
+        let mut printer = LinesPrinter::new(LinesOpts {
+            before,
+            after,
+            full_lines: false,
+            multiline: Multiline::Keep,
+            color: Coloring::Never,
+            ranges: false,
+            byte_offset: false,
+            show_query: false,
+        });
+        let mut out = termcolor::NoColor::new(Vec::new());
+        printer.write(&mut out, &extracted).unwrap();
+
+        String::from_utf8(out.into_inner()).unwrap()
+    }
+
+    #[test]
+    #[cfg(feature = "javascript")]
+    fn overlapping_context_is_printed_once() {
+        assert_eq!(
+            lines_with_context(1, 1, "a;\nf(1, 2);\ng(3);\nb;\nc;\nd;\nh(4);\n"),
+            concat!(
+                "a.js:2:3:n:1\n",
+                "a.js:2:6:n:2\n",
+                "a.js:3:3:n:3\n",
+                "a.js-1-a;\n",
+                "a.js:2:f(1, 2);\n",
+                "a.js:3:g(3);\n",
+                "a.js-4-b;\n",
+                "--\n",
+                "a.js:7:3:n:4\n",
+                "a.js-6-d;\n",
+                "a.js:7:h(4);\n",
+            )
+        );
+    }
+
+    #[test]
+    #[cfg(all(feature = "javascript", feature = "html"))]
+    fn injected_matches_are_printed_in_source_order() {
+        use tree_grepper::{Language, Search};
+
+        let results: Vec<_> = Search::builder()
+            .query(Language::Html, "(text)@t")
+            .query(Language::JavaScript, "(number)@n")
+            .default_injections(true)
+            .keep_source(true)
+            .text(
+                b"<p>1</p>\n<script>\n  f(2);\n</script>\n<p>3</p>\n".to_vec(),
+                Some(std::path::PathBuf::from("a.html")),
+                Some(Language::Html),
+            )
+            .build()
+            .unwrap()
+            .run()
+            .unwrap()
+            .collect();
+
+        let mut printer = LinesPrinter::new(LinesOpts {
+            before: 1,
+            after: 1,
+            full_lines: false,
+            multiline: Multiline::Keep,
+            color: Coloring::Never,
+            ranges: false,
+            byte_offset: false,
+            show_query: false,
+        });
+        let mut out = termcolor::NoColor::new(Vec::new());
+        printer
+            .write(&mut out, results[0].as_ref().unwrap())
+            .unwrap();
+
+        assert_eq!(
+            String::from_utf8(out.into_inner()).unwrap(),
+            concat!(
+                "a.html:1:4:t:1\n",
+                "a.html:3:5:n:2\n",
+                "a.html:5:4:t:3\n",
+                "a.html:1:<p>1</p>\n",
+                "a.html-2-<script>\n",
+                "a.html:3:  f(2);\n",
+                "a.html-4-</script>\n",
+                "a.html:5:<p>3</p>\n",
+            )
+        );
+    }
+
+    #[test]
+    fn keep_leaves_newlines_alone() {
+        assert_eq!(Multiline::Keep.apply("a\nb"), "a\nb");
+    }
+
+    #[test]
+    fn escape_makes_one_line() {
+        assert_eq!(Multiline::Escape.apply("a\r\nb\\c"), "a\\r\\nb\\\\c");
+    }
+
+    #[test]
+    fn escape_does_not_allocate_for_single_lines() {
+        assert!(matches!(Multiline::Escape.apply("abc"), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn truncate_keeps_the_first_line() {
+        assert_eq!(Multiline::Truncate.apply("a\r\nb"), "a...");
+        assert_eq!(Multiline::Truncate.apply("abc"), "abc");
+    }
+}
@@ -1,57 +1,104 @@
 mod cli;
-mod extractor;
-mod extractor_chooser;
-mod language;
-
-use anyhow::{bail, Context, Result};
-use cli::{Invocation, QueryFormat, QueryOpts};
-use crossbeam::channel;
-use language::Language;
-use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+mod config;
+mod lines;
+mod records;
+mod sarif;
+mod tree;
+
+use anyhow::{Context, Result};
+use cli::{Invocation, OutputMode, QueryFormat, QueryOpts};
+use lines::{LinesOpts, LinesPrinter};
+use records::{Counts, GroupedFile, Record, Rewritten};
+use sarif::SarifLog;
+use serde::ser::{SerializeSeq, Serializer};
 use std::env;
 use std::io::{self, BufWriter, Write};
-use tree_sitter::Parser;
+use termcolor::{Ansi, NoColor, WriteColor};
+use tree_grepper::{ExtractedFile, FileError, FileTypes, Language, Severity};
+
+// We use the same exit codes as grep, so tree-grepper can be used in shell
+// conditionals the same way.
+
+/// Exit code for when we found at least one match.
+const EXIT_MATCHES: i32 = 0;
+
+/// Exit code for when we searched everything and found no matches.
+const EXIT_NO_MATCHES: i32 = 1;
 
-#[global_allocator]
-static ALLOCATOR: bump_alloc::BumpAlloc = bump_alloc::BumpAlloc::new();
+/// Exit code for when we had a problem, either one that made us stop or one
+/// with a file we couldn't search along the way.
+const EXIT_ERROR: i32 = 2;
+
+/// Exit code for when we checked rules and nothing was at least as severe as
+/// `--fail-on`.
+const EXIT_RULES_PASSED: i32 = 0;
+
+/// Exit code for when we checked rules and found a match at least as severe
+/// as `--fail-on`.
+const EXIT_RULES_FAILED: i32 = 1;
 
 fn main() {
+    let terminal = atty::is(atty::Stream::Stdout);
     let mut buffer = BufWriter::new(io::stdout());
 
-    if let Err(error) = try_main(env::args().collect(), &mut buffer) {
-        if let Some(err) = error.downcast_ref::<io::Error>() {
+    match try_main(env::args().collect(), &mut buffer, terminal) {
+        Ok(exit_code) => {
+            // see below for why a broken pipe is fine.
+            if let Err(err) = buffer.flush() {
+                if err.kind() != io::ErrorKind::BrokenPipe {
+                    panic!("failed to flush buffer! {:?}", err);
+                }
+            }
+            std::process::exit(exit_code);
+        }
+
+        Err(error) => {
             // a broken pipe is totally normal and fine. It's what we get when
             // we pipe to something like `head` that only takes a certain number
-            // of lines.
-            if err.kind() == io::ErrorKind::BrokenPipe {
-                std::process::exit(0);
+            // of lines. Queries handle this themselves so they can exit
+            // according to what they found, so we only get here from the
+            // listings, which always succeed.
+            if is_broken_pipe(&error) {
+                std::process::exit(EXIT_MATCHES);
             }
-        }
 
-        if let Some(clap_error) = error.downcast_ref::<clap::Error>() {
-            // Clap errors (--help or misuse) are already well-formatted,
-            // so we don't have to do any additional work.
-            eprint!("{}", clap_error);
-        } else {
-            eprintln!("{:?}", error);
-        }
+            if let Some(clap_error) = error.downcast_ref::<clap::Error>() {
+                // Clap errors (--help or misuse) are already well-formatted,
+                // so we don't have to do any additional work. They also know
+                // where to print and what to exit with (0 for `--help`, and 2
+                // for misuse, same as us.)
+                clap_error.exit();
+            } else {
+                eprintln!("{:?}", error);
+            }
 
-        std::process::exit(1);
+            std::process::exit(EXIT_ERROR);
+        }
     }
-
-    buffer.flush().expect("failed to flush buffer!");
 }
 
-fn try_main(args: Vec<String>, out: impl Write) -> Result<()> {
+/// Returns the exit code we should use if everything went OK. `terminal` says
+/// whether `out` ends up on a terminal, which decides whether we use color by
+/// default.
+fn try_main(args: Vec<String>, out: impl Write, terminal: bool) -> Result<i32> {
     let invocation = Invocation::from_args(args)
         .context("couldn't get a valid configuration from the command-line options")?;
 
     match invocation {
         Invocation::DoQuery(query_opts) => {
-            do_query(query_opts, out).context("couldn't perform the query")
+            do_query(query_opts, out, terminal).context("couldn't perform the query")
         }
         Invocation::ShowLanguages => {
-            show_languages(out).context("couldn't show the list of languages")
+            show_languages(out).context("couldn't show the list of languages")?;
+            Ok(EXIT_MATCHES)
+        }
+        Invocation::ShowTypes(file_types) => {
+            show_types(out, &file_types).context("couldn't show the list of file types")?;
+            Ok(EXIT_MATCHES)
+        }
+        Invocation::ShowTree(opts) => {
+            tree::show_tree(&opts, out).context("couldn't show the syntax tree")?;
+            Ok(EXIT_MATCHES)
         }
     }
 }
@@ -64,109 +111,332 @@ fn show_languages(mut out: impl Write) -> Result<()> {
     Ok(())
 }
 
-fn do_query(opts: QueryOpts, mut out: impl Write) -> Result<()> {
-    // You might think "why not use ParallelBridge here?" Well, the quick answer
-    // is that I benchmarked it and having things separated here and handling
-    // their own errors actually speeds up this part of the code by like 20%!
-    let items: Vec<ignore::DirEntry> =
-        find_files(&opts).context("had a problem while walking the filesystem")?;
-
-    let chooser = opts
-        .extractor_chooser()
-        .context("couldn't construct a filetype matcher")?;
-
-    let mut extracted_files = items
-        .par_iter()
-        .filter_map(|entry| {
-            chooser
-                .extractor_for(entry)
-                .map(|extractor| (entry, extractor))
-        })
-        .map_init(Parser::new, |parser, (entry, extractor)| {
-            extractor
-                .extract_from_file(entry.path(), parser)
-                .with_context(|| {
-                    format!("could not extract matches from {}", entry.path().display())
+fn show_types(mut out: impl Write, file_types: &FileTypes) -> Result<()> {
+    for (language, globs) in file_types.globs(&Language::all())? {
+        writeln!(out, "{}: {}", language, globs.join(", "))
+            .context("couldn't print a file type")?;
+    }
+
+    Ok(())
+}
+
+fn do_query(opts: QueryOpts, out: impl Write, terminal: bool) -> Result<i32> {
+    let results = opts.search.run()?;
+    let mut summary = Summary::new(opts.strict, opts.fail_on);
+
+    if opts.quiet {
+        for result in results {
+            match result {
+                // returning here drops `results`, which stops the search
+                // instead of waiting for the rest of the files.
+                Ok(extracted_file) if summary.fails(&extracted_file) => {
+                    return Ok(summary.failing_exit_code())
+                }
+                Ok(_) => (),
+                Err(error) => eprintln!("{}", summary.check(error)?),
+            }
+        }
+
+        return Ok(summary.exit_code());
+    }
+
+    // Sorting is the only thing that makes us wait for all the results before
+    // we print anything. Otherwise, we print files as soon as they come in.
+    let results: Box<dyn Iterator<Item = Result<ExtractedFile, FileError>>> = if opts.sort {
+        let mut extracted_files = Vec::new();
+        let mut errors = Vec::new();
+
+        for result in results {
+            match result {
+                Ok(extracted_file) => extracted_files.push(extracted_file),
+                Err(error) => errors.push(error),
+            }
+        }
+
+        extracted_files.sort();
+        errors.sort_by(|a, b| a.path().cmp(&b.path()));
+
+        Box::new(
+            extracted_files
+                .into_iter()
+                .map(Ok)
+                .chain(errors.into_iter().map(Err)),
+        )
+    } else {
+        Box::new(results)
+    };
+
+    let records: Box<dyn Iterator<Item = Result<Record, FileError>>> = match opts.mode {
+        OutputMode::Matches => Box::new(results.map(|result| result.map(Record::Matches))),
+        OutputMode::GroupedMatches => Box::new(results.map(|result| {
+            result.map(|extracted_file| Record::GroupedMatches(GroupedFile(extracted_file)))
+        })),
+        OutputMode::Count { by_capture } => Box::new(Counts::new(results, by_capture)),
+        OutputMode::FilesWithMatches => Box::new(
+            results
+                .map(|result| result.map(|extracted_file| Record::FileName(extracted_file.into()))),
+        ),
+        OutputMode::FilesWithoutMatch => Box::new(
+            results
+                .filter(|result| match result {
+                    Ok(extracted_file) => extracted_file.matches().is_empty(),
+                    Err(_) => true,
+                })
+                .map(|result| result.map(|extracted_file| Record::FileName(extracted_file.into()))),
+        ),
+        OutputMode::ParseErrors => Box::new(
+            results
+                .filter(|result| match result {
+                    Ok(extracted_file) => extracted_file.has_errors(),
+                    Err(_) => true,
                 })
-        })
-        .filter_map(|result_containing_option| match result_containing_option {
-            Ok(None) => None,
-            Ok(Some(extraction)) => Some(Ok(extraction)),
-            Err(err) => Some(Err(err)),
-        })
-        .collect::<Result<Vec<extractor::ExtractedFile>>>()
-        .context("couldn't extract matches from files")?;
+                .map(|result| {
+                    result.map(|extracted_file| Record::ParseErrors(extracted_file.into()))
+                }),
+        ),
+        OutputMode::Rewrite { in_place } => Box::new(
+            results
+                .filter(|result| match result {
+                    Ok(extracted_file) => !extracted_file.edits().is_empty(),
+                    Err(_) => true,
+                })
+                .map(move |result| {
+                    result
+                        .and_then(|extracted_file| {
+                            if in_place {
+                                Rewritten::apply(extracted_file)
+                            } else {
+                                Ok(Rewritten::dry_run(extracted_file))
+                            }
+                        })
+                        .map(Record::Rewrite)
+                }),
+        ),
+    };
 
-    if opts.sort {
-        extracted_files.sort()
+    match write_records(
+        opts.format,
+        opts.lines,
+        out,
+        terminal,
+        records,
+        &mut summary,
+    ) {
+        Ok(()) => Ok(summary.exit_code()),
+        // whoever was reading our output has seen enough, so we exit the same
+        // way we would have if we'd stopped searching here.
+        Err(error) if is_broken_pipe(&error) => Ok(summary.exit_code()),
+        Err(error) => Err(error),
     }
+}
 
-    match opts.format {
+fn is_broken_pipe(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        matches!(
+            cause.downcast_ref::<io::Error>(),
+            Some(err) if err.kind() == io::ErrorKind::BrokenPipe
+        )
+    })
+}
+
+fn write_records(
+    format: QueryFormat,
+    lines: LinesOpts,
+    mut out: impl Write,
+    terminal: bool,
+    records: impl Iterator<Item = Result<Record, FileError>>,
+    summary: &mut Summary,
+) -> Result<()> {
+    match format {
         QueryFormat::Lines => {
-            for extracted_file in extracted_files {
-                write!(out, "{}", extracted_file).context("could not write lines")?;
+            let color = lines.color.enabled(terminal);
+            let printer = LinesPrinter::new(lines);
+
+            if color {
+                write_lines(Ansi::new(out), printer, records, summary)?;
+            } else {
+                write_lines(NoColor::new(out), printer, records, summary)?;
             }
         }
 
         QueryFormat::Json => {
-            serde_json::to_writer(out, &extracted_files).context("could not write JSON output")?;
+            write_json_array(&mut serde_json::Serializer::new(out), records, summary)?;
         }
 
         QueryFormat::JsonLines => {
-            for extracted_file in extracted_files {
-                writeln!(
-                    out,
-                    "{}",
-                    serde_json::to_string(&extracted_file)
-                        .context("could not write JSON output")?
-                )
-                .context("could not write line")?;
+            for result in records {
+                let line = match result {
+                    Ok(record) => serde_json::to_string(&summary.found(record)),
+                    Err(error) => serde_json::to_string(&summary.check(error)?),
+                }
+                .context("could not write JSON output")?;
+
+                writeln!(out, "{}", line).context("could not write line")?;
+                out.flush().context("could not flush output")?;
             }
         }
 
         QueryFormat::PrettyJson => {
-            serde_json::to_writer_pretty(out, &extracted_files)
-                .context("could not write JSON output")?;
+            write_json_array(&mut serde_json::Serializer::pretty(out), records, summary)?;
+        }
+
+        QueryFormat::Sarif => {
+            let mut log = SarifLog::default();
+
+            for result in records {
+                match result {
+                    Ok(record) => {
+                        if let Record::Matches(extracted_file) = summary.found(record) {
+                            log.add_file(&extracted_file)
+                        }
+                    }
+                    Err(error) => log.add_error(&summary.check(error)?),
+                }
+            }
+
+            log.write(out)?;
         }
     }
 
     Ok(())
 }
 
-fn find_files(opts: &QueryOpts) -> Result<Vec<ignore::DirEntry>> {
-    let mut builder = match opts.paths.split_first() {
-        Some((first, rest)) => {
-            let mut builder = ignore::WalkBuilder::new(first);
-            for path in rest {
-                builder.add(path);
+/// Write records one or more lines at a time, flushing as we go so matches
+/// show up as soon as we find them.
+fn write_lines(
+    mut out: impl WriteColor,
+    mut printer: LinesPrinter,
+    records: impl Iterator<Item = Result<Record, FileError>>,
+    summary: &mut Summary,
+) -> Result<()> {
+    for result in records {
+        match result {
+            Ok(record) => match summary.found(record) {
+                Record::Matches(extracted_file) => printer.write(&mut out, &extracted_file),
+                Record::GroupedMatches(GroupedFile(extracted_file)) => {
+                    printer.write_grouped(&mut out, &extracted_file)
+                }
+                other => write!(out, "{}", other),
             }
+            .context("could not write lines")?,
+            Err(error) => eprintln!("{}", summary.check(error)?),
+        }
 
-            builder
+        out.flush().context("could not flush output")?;
+    }
+
+    Ok(())
+}
+
+/// Write a JSON array one item at a time, so we don't have to hold all the
+/// records in memory to print them. Files we couldn't search show up as error
+/// records alongside the rest.
+fn write_json_array<W, F>(
+    serializer: &mut serde_json::Serializer<W, F>,
+    records: impl Iterator<Item = Result<Record, FileError>>,
+    summary: &mut Summary,
+) -> Result<()>
+where
+    W: Write,
+    F: serde_json::ser::Formatter,
+{
+    let mut seq = serializer
+        .serialize_seq(None)
+        .context("could not write JSON output")?;
+
+    for result in records {
+        match result {
+            Ok(record) => seq.serialize_element(&summary.found(record)),
+            Err(error) => seq.serialize_element(&summary.check(error)?),
         }
-        None => bail!("I need at least one file or directory to walk!"),
-    };
+        .context("could not write JSON output")?;
+    }
 
-    let (root_sender, receiver) = channel::unbounded();
-
-    builder
-        .git_ignore(opts.git_ignore)
-        .git_exclude(opts.git_ignore)
-        .git_global(opts.git_ignore)
-        .build_parallel()
-        .run(|| {
-            let sender = root_sender.clone();
-            Box::new(move |entry_result| match entry_result {
-                Ok(entry) => match sender.send(entry) {
-                    Ok(()) => ignore::WalkState::Continue,
-                    Err(_) => ignore::WalkState::Quit,
-                },
-                Err(_) => ignore::WalkState::Quit,
-            })
-        });
+    seq.end().context("could not write JSON output")
+}
+
+/// Keeps track of what we found so we know what exit code to use, and stops
+/// the search at the first file we couldn't search if we're running with
+/// `--strict`.
+///
+/// When we're checking rules, exit codes work like a linter's instead of
+/// grep's: 0 if nothing matched at or above `fail_on`, 1 if something did, and
+/// 2 if we couldn't search some files.
+struct Summary {
+    strict: bool,
+    fail_on: Option<Severity>,
+    matched: bool,
+    highest_severity: Option<Severity>,
+    failures: usize,
+}
+
+impl Summary {
+    fn new(strict: bool, fail_on: Option<Severity>) -> Summary {
+        Summary {
+            strict,
+            fail_on,
+            matched: false,
+            highest_severity: None,
+            failures: 0,
+        }
+    }
+
+    fn found(&mut self, record: Record) -> Record {
+        if record.is_match() {
+            self.matched = true;
+        }
+
+        self.highest_severity = self.highest_severity.max(record.severity());
+
+        record
+    }
+
+    /// Is this file enough to decide the exit code on its own? (For
+    /// `--quiet`, which stops as soon as it is.)
+    fn fails(&self, extracted_file: &ExtractedFile) -> bool {
+        match self.fail_on {
+            Some(fail_on) => extracted_file.highest_severity() >= Some(fail_on),
+            None => !extracted_file.matches().is_empty(),
+        }
+    }
+
+    fn failing_exit_code(&self) -> i32 {
+        match self.fail_on {
+            Some(_) => EXIT_RULES_FAILED,
+            None => EXIT_MATCHES,
+        }
+    }
 
-    drop(root_sender);
+    fn check(&mut self, error: FileError) -> Result<FileError> {
+        // we skip files because we were asked to, so that's not a problem
+        // with the search.
+        if error.skipped().is_some() {
+            return Ok(error);
+        }
+
+        if self.strict {
+            return Err(error).context("stopping at the first error because of --strict");
+        }
 
-    Ok(receiver.iter().collect())
+        self.failures += 1;
+        Ok(error)
+    }
+
+    fn exit_code(&self) -> i32 {
+        if self.failures > 0 {
+            EXIT_ERROR
+        } else if let Some(fail_on) = self.fail_on {
+            if self.highest_severity >= Some(fail_on) {
+                EXIT_RULES_FAILED
+            } else {
+                EXIT_RULES_PASSED
+            }
+        } else if self.matched {
+            EXIT_MATCHES
+        } else {
+            EXIT_NO_MATCHES
+        }
+    }
 }
 
 #[cfg(test)]
@@ -174,17 +444,59 @@ mod tests {
     use super::*;
 
     fn call(args: &[&str]) -> String {
+        call_with_exit_code(args).1
+    }
+
+    fn call_with_exit_code(args: &[&str]) -> (i32, String) {
         let mut bytes = Vec::new();
-        try_main(
+        let exit_code = try_main(
             args.iter().map(|s| s.to_string()).collect(),
             Box::new(&mut bytes),
+            false,
         )
         .unwrap();
 
-        String::from_utf8(bytes).unwrap()
+        (exit_code, String::from_utf8(bytes).unwrap())
+    }
+
+    #[test]
+    fn languages_lists_what_was_compiled_in() {
+        let out = call(&["tree-grepper", "--languages"]);
+        let languages: Vec<&str> = out.lines().collect();
+
+        assert_eq!(languages.contains(&"elm"), cfg!(feature = "elm"));
+        assert_eq!(languages.contains(&"rust"), cfg!(feature = "rust"));
+        assert_eq!(languages.contains(&"tsx"), cfg!(feature = "tsx"));
     }
 
     #[test]
+    #[cfg(all(feature = "javascript", feature = "typescript"))]
+    fn type_list_shows_added_and_cleared_types() {
+        let out = call(&[
+            "tree-grepper",
+            "--type-add",
+            "javascript:*.jsm",
+            "--type-clear",
+            "typescript",
+            "--type-list",
+        ]);
+        let types: Vec<&str> = out.lines().collect();
+
+        // the rest of the JavaScript globs come from the ignore crate, and
+        // change from version to version.
+        let javascript: Vec<&str> = types
+            .iter()
+            .find_map(|line| line.strip_prefix("javascript: "))
+            .unwrap()
+            .split(", ")
+            .collect();
+        assert!(javascript.contains(&"*.js"));
+        assert!(javascript.contains(&"*.jsm"));
+        assert!(types.contains(&"typescript: "));
+    }
+
+    #[test]
+    #[cfg(feature = "elm")]
     fn lines_output() {
         insta::assert_snapshot!(call(&[
             "tree-grepper",
@@ -200,6 +512,7 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "elm")]
     fn json_output() {
         insta::assert_snapshot!(call(&[
             "tree-grepper",
@@ -215,6 +528,7 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "javascript")]
     fn json_lines_output() {
         insta::assert_snapshot!(call(&[
             "tree-grepper",
@@ -229,6 +543,7 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "elm")]
     fn pretty_json_output() {
         insta::assert_snapshot!(call(&[
             "tree-grepper",
@@ -242,10 +557,621 @@ mod tests {
         ]))
     }
 
+    #[test]
+    #[cfg(feature = "elm")]
+    fn count_output() {
+        assert_eq!(
+            call(&[
+                "tree-grepper",
+                "-q",
+                "elm",
+                "(import_clause)",
+                "--count",
+                "--sort",
+                "--no-gitignore",
+                "vendor/tree-sitter-elm/examples",
+            ]),
+            "vendor/tree-sitter-elm/examples/basic.elm:3\ntotal:3\n",
+        )
+    }
+
+    #[test]
+    #[cfg(feature = "elm")]
+    fn count_json_lines_output() {
+        assert_eq!(
+            call(&[
+                "tree-grepper",
+                "-q",
+                "elm",
+                "(import_clause)@import",
+                "--count",
+                "--by-capture",
+                "-f",
+                "json-lines",
+                "--sort",
+                "--no-gitignore",
+                "vendor/tree-sitter-elm/examples",
+            ]),
+            concat!(
+                r#"{"file":"vendor/tree-sitter-elm/examples/basic.elm","file_type":"elm","count":3,"captures":{"import":3}}"#,
+                "\n",
+                r#"{"total":3,"captures":{"import":3}}"#,
+                "\n",
+            ),
+        )
+    }
+
+    #[test]
+    #[cfg(feature = "elm")]
+    fn files_with_matches_output() {
+        assert_eq!(
+            call(&[
+                "tree-grepper",
+                "-q",
+                "elm",
+                "(import_clause)",
+                "--files-with-matches",
+                "--sort",
+                "--no-gitignore",
+                "vendor/tree-sitter-elm/examples",
+            ]),
+            "vendor/tree-sitter-elm/examples/basic.elm\n",
+        )
+    }
+
+    #[test]
+    #[cfg(feature = "elm")]
+    fn files_without_match_output() {
+        assert_eq!(
+            call(&[
+                "tree-grepper",
+                "-q",
+                "elm",
+                "(import_clause)",
+                "--files-without-match",
+                "--sort",
+                "--no-gitignore",
+                "vendor/tree-sitter-elm/examples",
+            ]),
+            "vendor/tree-sitter-elm/examples/test.elm\n",
+        )
+    }
+
+    #[test]
+    #[cfg(feature = "elm")]
+    fn exit_code_for_matches() {
+        let (exit_code, _) = call_with_exit_code(&[
+            "tree-grepper",
+            "-q",
+            "elm",
+            "(import_clause)",
+            "--no-gitignore",
+            "vendor/tree-sitter-elm/examples",
+        ]);
+
+        assert_eq!(exit_code, EXIT_MATCHES);
+    }
+
+    #[test]
+    #[cfg(feature = "elm")]
+    fn exit_code_for_no_matches() {
+        let (exit_code, out) = call_with_exit_code(&[
+            "tree-grepper",
+            "-q",
+            "elm",
+            "(port_annotation)",
+            "--no-gitignore",
+            "vendor/tree-sitter-elm/examples",
+        ]);
+
+        assert_eq!(exit_code, EXIT_NO_MATCHES);
+        assert_eq!(out, "");
+    }
+
+    #[test]
+    fn lang_without_stdin_is_an_error() {
+        let error = try_main(
+            [
+                "tree-grepper",
+                "-q",
+                "rust",
+                "(identifier)",
+                "--lang",
+                "rust",
+                "src",
+            ]
+            .iter()
+            .map(|s| s.to_string())
+            .collect(),
+            Vec::new(),
+            false,
+        )
+        .unwrap_err();
+
+        assert!(format!("{:?}", error).contains("--lang only makes sense when searching stdin"));
+    }
+
+    #[test]
+    #[cfg(feature = "rust")]
+    fn exit_code_for_broken_pipe_depends_on_matches() {
+        /// Like a pipe to `head -c BYTES`, which goes away once it has seen
+        /// enough.
+        struct Head(usize);
+
+        impl Write for Head {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                if self.0 == 0 {
+                    return Err(io::ErrorKind::BrokenPipe.into());
+                }
+
+                let taken = buf.len().min(self.0);
+                self.0 -= taken;
+                Ok(taken)
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let exit_code = |query: &str| {
+            try_main(
+                [
+                    "tree-grepper",
+                    "-q",
+                    "rust",
+                    query,
+                    "-f",
+                    "json",
+                    "src/tree.rs",
+                ]
+                .iter()
+                .map(|s| s.to_string())
+                .collect(),
+                Head(1),
+                false,
+            )
+            .unwrap()
+        };
+
+        assert_eq!(exit_code("(function_item)"), EXIT_MATCHES);
+        assert_eq!(exit_code("(macro_definition)"), EXIT_NO_MATCHES);
+    }
+
+    #[test]
+    #[cfg(feature = "elm")]
+    fn quiet_prints_nothing() {
+        let (exit_code, out) = call_with_exit_code(&[
+            "tree-grepper",
+            "-q",
+            "elm",
+            "(import_clause)",
+            "--quiet",
+            "--no-gitignore",
+            "vendor/tree-sitter-elm/examples",
+        ]);
+
+        assert_eq!(exit_code, EXIT_MATCHES);
+        assert_eq!(out, "");
+    }
+
+    #[test]
+    #[cfg(feature = "elm")]
+    fn lines_output_with_ranges() {
+        let out = call(&[
+            "tree-grepper",
+            "-q",
+            "elm",
+            "(import_clause)",
+            "--ranges",
+            "--byte-offset",
+            "--sort",
+            "--no-gitignore",
+            "vendor/tree-sitter-elm/examples",
+        ]);
+
+        assert_eq!(
+            out.lines().next(),
+            Some("vendor/tree-sitter-elm/examples/basic.elm:3:1-3:15:52-66:query:import Browser"),
+        )
+    }
+
+    #[test]
+    #[cfg(feature = "elm")]
+    fn lines_output_with_zero_based_coordinates() {
+        let out = call(&[
+            "tree-grepper",
+            "-q",
+            "elm",
+            "(import_clause)",
+            "--coordinates=zero-based",
+            "--sort",
+            "--no-gitignore",
+            "vendor/tree-sitter-elm/examples",
+        ]);
+
+        assert_eq!(
+            out.lines().next(),
+            Some("vendor/tree-sitter-elm/examples/basic.elm:2:0:query:import Browser"),
+        )
+    }
+
+    #[test]
+    #[cfg(feature = "elm")]
+    fn show_query_prints_labels_or_indexes() {
+        let out = call(&[
+            "tree-grepper",
+            "-q",
+            "elm",
+            "(import_clause (upper_case_qid)@module (#eq? @module Browser))",
+            "-q",
+            "elm:html",
+            "(import_clause (upper_case_qid)@module (#eq? @module Html))",
+            "--show-query",
+            "--sort",
+            "--no-gitignore",
+            "vendor/tree-sitter-elm/examples",
+        ]);
+
+        assert_eq!(
+            out,
+            concat!(
+                "vendor/tree-sitter-elm/examples/basic.elm:3:8:0:module:Browser\n",
+                "vendor/tree-sitter-elm/examples/basic.elm:4:8:html:module:Html\n",
+            )
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "elm")]
+    fn group_by_match_keeps_captures_together() {
+        let out = call(&[
+            "tree-grepper",
+            "-q",
+            "elm",
+            "(import_clause (upper_case_qid)@module (exposing_list)@exposing)",
+            "--group-by-match",
+            "--sort",
+            "--no-gitignore",
+            "vendor/tree-sitter-elm/examples",
+        ]);
+
+        assert!(out.starts_with(concat!(
+            "vendor/tree-sitter-elm/examples/basic.elm:4:8: match 0 (pattern 0)\n",
+            "  @module 4:8: Html\n",
+            "  @exposing 4:13: exposing (Html, button, div, text)\n",
+            "vendor/tree-sitter-elm/examples/basic.elm:5:8: match 1 (pattern 0)\n",
+        )));
+    }
+
+    #[test]
+    #[cfg(feature = "elm")]
+    fn sarif_output() {
+        let out = call(&[
+            "tree-grepper",
+            "-q",
+            "elm",
+            "(import_clause (upper_case_qid)@module)",
+            "-f",
+            "sarif",
+            "--sort",
+            "--no-gitignore",
+            "vendor/tree-sitter-elm/examples",
+        ]);
+
+        let log: serde_json::Value = serde_json::from_str(&out).unwrap();
+        assert_eq!(log["version"], "2.1.0");
+
+        let run = &log["runs"][0];
+        assert_eq!(run["tool"]["driver"]["rules"][0]["id"], "module");
+        assert_eq!(run["invocations"][0]["executionSuccessful"], true);
+
+        let result = &run["results"][0];
+        assert_eq!(result["ruleId"], "module");
+        assert_eq!(
+            result["locations"][0]["physicalLocation"]["artifactLocation"]["uri"],
+            "vendor/tree-sitter-elm/examples/basic.elm"
+        );
+        assert_eq!(
+            result["locations"][0]["physicalLocation"]["region"],
+            serde_json::json!({
+                "startLine": 3,
+                "startColumn": 8,
+                "endLine": 3,
+                "endColumn": 15,
+                "byteOffset": 59,
+                "byteLength": 7,
+            })
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "elm")]
+    fn color_never_is_plain() {
+        assert_eq!(
+            call(&[
+                "tree-grepper",
+                "-q",
+                "elm",
+                "(import_clause)",
+                "--color=never",
+                "--sort",
+                "--no-gitignore",
+                "vendor/tree-sitter-elm/examples",
+            ]),
+            call(&[
+                "tree-grepper",
+                "-q",
+                "elm",
+                "(import_clause)",
+                "--sort",
+                "--no-gitignore",
+                "vendor/tree-sitter-elm/examples",
+            ]),
+        )
+    }
+
+    #[test]
+    #[cfg(feature = "elm")]
+    fn color_always_highlights_matches_in_source_lines() {
+        let out = call(&[
+            "tree-grepper",
+            "-q",
+            "elm",
+            "(import_clause (upper_case_qid)@name)",
+            "--color=always",
+            "--full-lines",
+            "--sort",
+            "--no-gitignore",
+            "vendor/tree-sitter-elm/examples",
+        ]);
+
+        assert!(out.contains("\x1b[0m\x1b[35mvendor/tree-sitter-elm/examples/basic.elm"));
+        // the source line shows the import with just the module name highlighted
+        assert!(out.contains(
+            ":import \x1b[0m\x1b[1m\x1b[31mHtml\x1b[0m exposing (Html, button, div, text)\n"
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "elm")]
+    fn rewrite_prints_a_diff() {
+        let (exit_code, out) = call_with_exit_code(&[
+            "tree-grepper",
+            "-q",
+            "elm",
+            "(import_clause (upper_case_qid)@module (#eq? @module Browser))",
+            "--rewrite",
+            "@module",
+            "Platform.@module",
+            "--sort",
+            "--no-gitignore",
+            "vendor/tree-sitter-elm/examples",
+        ]);
+
+        assert_eq!(exit_code, EXIT_MATCHES);
+        assert!(out.starts_with(concat!(
+            "--- a/vendor/tree-sitter-elm/examples/basic.elm\n",
+            "+++ b/vendor/tree-sitter-elm/examples/basic.elm\n",
+        )));
+        assert!(out.contains("\n-import Browser\n+import Platform.Browser\n"));
+    }
+
+    #[test]
+    #[cfg(feature = "elm")]
+    fn rules_print_like_diagnostics_and_fail_on_severity() {
+        let dir = std::env::temp_dir().join(format!("tree-grepper-rules-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let rules = dir.join("rules.toml");
+        std::fs::write(
+            &rules,
+            concat!(
+                "[[rules]]\n",
+                "id = \"no-browser\"\n",
+                "language = \"elm\"\n",
+                "query = \"(import_clause (upper_case_qid)@module (#eq? @module Browser))\"\n",
+                "message = \"use Platform instead\"\n",
+            ),
+        )
+        .unwrap();
+
+        let args = |fail_on: &'static str| {
+            [
+                "tree-grepper",
+                "--rules",
+                rules.to_str().unwrap(),
+                "--fail-on",
+                fail_on,
+                "--sort",
+                "--no-gitignore",
+                "vendor/tree-sitter-elm/examples",
+            ]
+        };
+
+        let (exit_code, out) = call_with_exit_code(&args("warning"));
+        assert_eq!(exit_code, EXIT_RULES_FAILED);
+        assert_eq!(
+            out,
+            "vendor/tree-sitter-elm/examples/basic.elm:3:8: warning: use Platform instead [no-browser]\n"
+        );
+
+        let (exit_code, _) = call_with_exit_code(&args("error"));
+        assert_eq!(exit_code, EXIT_RULES_PASSED);
+    }
+
+    #[test]
+    #[cfg(feature = "rust")]
+    fn parse_errors_can_be_reported_or_skipped() {
+        let dir =
+            std::env::temp_dir().join(format!("tree-grepper-parse-errors-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("bad.rs"), "fn main() {\n    let x = ;\n}\n").unwrap();
+        std::fs::write(dir.join("good.rs"), "fn main() {}\n").unwrap();
+        let dir_str = dir.to_str().unwrap();
+
+        let (exit_code, out) = call_with_exit_code(&[
+            "tree-grepper",
+            "-q",
+            "rust",
+            "(identifier)@id",
+            "--report-parse-errors",
+            dir_str,
+        ]);
+        assert_eq!(exit_code, EXIT_MATCHES);
+        assert_eq!(
+            out,
+            format!("{}:2:11: ERROR\n", dir.join("bad.rs").display())
+        );
+
+        let (_, out) = call_with_exit_code(&[
+            "tree-grepper",
+            "-q",
+            "rust",
+            "(identifier)@id",
+            "--skip-files-with-errors",
+            "-f",
+            "json-lines",
+            dir_str,
+        ]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].contains(r#""has_errors":false"#));
+        assert!(lines[0].contains("good.rs"));
+    }
+
+    #[test]
+    #[cfg(feature = "rust")]
+    fn skipped_files_are_not_errors() {
+        let dir =
+            std::env::temp_dir().join(format!("tree-grepper-max-filesize-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("big.rs"), "fn main() {}\n".repeat(100)).unwrap();
+
+        let (exit_code, out) = call_with_exit_code(&[
+            "tree-grepper",
+            "-q",
+            "rust",
+            "(identifier)@id",
+            "--max-filesize",
+            "1K",
+            "--strict",
+            "-f",
+            "json-lines",
+            dir.to_str().unwrap(),
+        ]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(exit_code, EXIT_NO_MATCHES);
+        assert!(out.ends_with(
+            r#""skipped":"the file is 1300 bytes, over the limit of 1024 bytes"}
+"#
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "elm")]
+    fn missing_files_do_not_stop_the_search() {
+        let (exit_code, out) = call_with_exit_code(&[
+            "tree-grepper",
+            "-q",
+            "elm",
+            "(import_clause)",
+            "-f",
+            "json-lines",
+            "--sort",
+            "--no-gitignore",
+            "vendor/tree-sitter-elm/examples",
+            "does-not-exist",
+        ]);
+
+        assert_eq!(exit_code, EXIT_ERROR);
+
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with(r#"{"file":"vendor/tree-sitter-elm/examples/basic.elm""#));
+        assert!(lines[1].starts_with(r#"{"file":"does-not-exist","error":"#));
+    }
+
+    #[test]
+    #[cfg(feature = "elm")]
+    fn strict_stops_at_the_first_missing_file() {
+        let result = try_main(
+            [
+                "tree-grepper",
+                "-q",
+                "elm",
+                "(import_clause)",
+                "--strict",
+                "--no-gitignore",
+                "vendor/tree-sitter-elm/examples",
+                "does-not-exist",
+            ]
+            .iter()
+            .map(|s| s.to_string())
+            .collect(),
+            Vec::new(),
+            false,
+        );
+
+        assert!(result.is_err());
+    }
+
     // All languages should have a test that just spits out their entire node
     // tree. We use this to know about changes in the vendored parsers!
 
+    // Not every grammar comes with example files we can use, so some of
+    // these look at our own instead, in tests/fixtures.
+    #[test]
+    #[cfg(feature = "bash")]
+    fn all_bash() {
+        insta::assert_snapshot!(call(&[
+            "tree-grepper",
+            "-q",
+            "bash",
+            "(_)",
+            "--format=pretty-json",
+            "--sort",
+            "--no-gitignore",
+            "tests/fixtures/bash",
+        ]))
+    }
+
     #[test]
+    #[cfg(feature = "c")]
+    fn all_c() {
+        insta::assert_snapshot!(call(&[
+            "tree-grepper",
+            "-q",
+            "c",
+            "(_)",
+            "--format=pretty-json",
+            "--sort",
+            "--no-gitignore",
+            "tests/fixtures/c",
+        ]))
+    }
+
+    #[test]
+    #[cfg(feature = "csharp")]
+    fn all_csharp() {
+        insta::assert_snapshot!(call(&[
+            "tree-grepper",
+            "-q",
+            "csharp",
+            "(_)",
+            "--format=pretty-json",
+            "--sort",
+            "--no-gitignore",
+            "tests/fixtures/csharp",
+        ]))
+    }
+
+    #[test]
+    #[cfg(feature = "cpp")]
     fn all_cpp() {
         insta::assert_snapshot!(call(&[
             "tree-grepper",
@@ -260,6 +1186,22 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "css")]
+    fn all_css() {
+        insta::assert_snapshot!(call(&[
+            "tree-grepper",
+            "-q",
+            "css",
+            "(_)",
+            "--format=pretty-json",
+            "--sort",
+            "--no-gitignore",
+            "tests/fixtures/css",
+        ]))
+    }
+
+    #[test]
+    #[cfg(feature = "elm")]
     fn all_elm() {
         insta::assert_snapshot!(call(&[
             "tree-grepper",
@@ -274,6 +1216,22 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "go")]
+    fn all_go() {
+        insta::assert_snapshot!(call(&[
+            "tree-grepper",
+            "-q",
+            "go",
+            "(_)",
+            "--format=pretty-json",
+            "--sort",
+            "--no-gitignore",
+            "tests/fixtures/go",
+        ]))
+    }
+
+    #[test]
+    #[cfg(feature = "haskell")]
     fn all_haskell() {
         insta::assert_snapshot!(call(&[
             "tree-grepper",
@@ -288,6 +1246,37 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "html")]
+    fn all_html() {
+        insta::assert_snapshot!(call(&[
+            "tree-grepper",
+            "-q",
+            "html",
+            "(_)",
+            "--format=pretty-json",
+            "--sort",
+            "--no-gitignore",
+            "tests/fixtures/html",
+        ]))
+    }
+
+    #[test]
+    #[cfg(feature = "java")]
+    fn all_java() {
+        insta::assert_snapshot!(call(&[
+            "tree-grepper",
+            "-q",
+            "java",
+            "(_)",
+            "--format=pretty-json",
+            "--sort",
+            "--no-gitignore",
+            "tests/fixtures/java",
+        ]))
+    }
+
+    #[test]
+    #[cfg(feature = "javascript")]
     fn all_javascript() {
         insta::assert_snapshot!(call(&[
             "tree-grepper",
@@ -307,6 +1296,25 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "json")]
+    fn all_json() {
+        insta::assert_snapshot!(call(&[
+            "tree-grepper",
+            "-q",
+            "json",
+            "(_)",
+            "--format=pretty-json",
+            "--sort",
+            "--no-gitignore",
+            // the grammar repo is mostly generated JSON files, which would make
+            // for an enormous snapshot. package.json is enough to see that it
+            // works.
+            "tests/fixtures/json",
+        ]))
+    }
+
+    #[test]
+    #[cfg(feature = "php")]
     fn all_php() {
         insta::assert_snapshot!(call(&[
             "tree-grepper",
@@ -321,6 +1329,22 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "python")]
+    fn all_python() {
+        insta::assert_snapshot!(call(&[
+            "tree-grepper",
+            "-q",
+            "python",
+            "(_)",
+            "--format=pretty-json",
+            "--sort",
+            "--no-gitignore",
+            "tests/fixtures/python",
+        ]))
+    }
+
+    #[test]
+    #[cfg(feature = "ruby")]
     fn all_ruby() {
         insta::assert_snapshot!(call(&[
             "tree-grepper",
@@ -335,6 +1359,7 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "rust")]
     fn all_rust() {
         insta::assert_snapshot!(call(&[
             "tree-grepper",
@@ -349,6 +1374,7 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "typescript")]
     fn all_typescript() {
         insta::assert_snapshot!(call(&[
             "tree-grepper",
@@ -369,6 +1395,22 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "tsx")]
+    fn all_tsx() {
+        insta::assert_snapshot!(call(&[
+            "tree-grepper",
+            "-q",
+            "tsx",
+            "(_)",
+            "--format=pretty-json",
+            "--sort",
+            "--no-gitignore",
+            "tests/fixtures/tsx",
+        ]))
+    }
+
+    #[test]
+    #[cfg(feature = "elixir")]
     fn all_elixir() {
         insta::assert_snapshot!(call(&[
             "tree-grepper",
@@ -381,4 +1423,19 @@ mod tests {
             "vendor/tree-sitter-elixir",
         ]))
     }
+
+    #[test]
+    #[cfg(feature = "yaml")]
+    fn all_yaml() {
+        insta::assert_snapshot!(call(&[
+            "tree-grepper",
+            "-q",
+            "yaml",
+            "(_)",
+            "--format=pretty-json",
+            "--sort",
+            "--no-gitignore",
+            "tests/fixtures/yaml",
+        ]))
+    }
 }
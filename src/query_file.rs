@@ -0,0 +1,149 @@
+use crate::language::Language;
+use anyhow::{bail, Context, Result};
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// A query loaded from a `.scm` file.
+#[derive(Debug)]
+pub(crate) struct QueryFile {
+    pub(crate) path: PathBuf,
+    pub(crate) language: Language,
+    pub(crate) source: String,
+}
+
+/// Load a query file, or every `.scm` file under a directory (in a stable
+/// order, so combined queries come out the same every time.)
+pub(crate) fn load(path: &Path) -> Result<Vec<QueryFile>> {
+    if !path.is_dir() {
+        return Ok(vec![load_file(path)?]);
+    }
+
+    let mut paths = Vec::new();
+    find_scm_files(path, &mut paths)
+        .with_context(|| format!("could not find query files in {}", path.display()))?;
+    paths.sort();
+
+    if paths.is_empty() {
+        bail!("there aren't any .scm files in {}", path.display())
+    }
+
+    paths.iter().map(|path| load_file(path)).collect()
+}
+
+fn find_scm_files(dir: &Path, paths: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+
+        if path.is_dir() {
+            find_scm_files(&path, paths)?;
+        } else if path.extension() == Some(OsStr::new("scm")) {
+            paths.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+fn load_file(path: &Path) -> Result<QueryFile> {
+    let source = fs::read_to_string(path)
+        .with_context(|| format!("could not read query file {}", path.display()))?;
+
+    let language = language_for(path, &source).with_context(|| {
+        format!(
+            "I couldn't tell what language {} is for. Add a `; language: NAME` comment at the top, or name it like `query.NAME.scm`.",
+            path.display()
+        )
+    })?;
+
+    Ok(QueryFile {
+        path: path.to_owned(),
+        language,
+        source,
+    })
+}
+
+/// Figure out what language a query file is for. In order, we look at:
+///
+/// 1. a `; language: NAME` comment before the query starts
+/// 2. a second extension, like `no-unwrap.rust.scm`
+/// 3. the name of the directory it's in, like `queries/rust/no-unwrap.scm`
+fn language_for(path: &Path, source: &str) -> Result<Language> {
+    for line in source.lines() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let comment = match line.strip_prefix(';') {
+            Some(comment) => comment.trim_start_matches(';').trim(),
+            None => break,
+        };
+
+        if let Some(name) = comment.strip_prefix("language:") {
+            return Language::from_str(name.trim());
+        }
+    }
+
+    let from_extension = path
+        .file_stem()
+        .and_then(|stem| Path::new(stem).extension())
+        .and_then(|ext| ext.to_str())
+        .and_then(|ext| Language::from_str(ext).ok());
+
+    let from_directory = || {
+        path.parent()
+            .and_then(|parent| parent.file_name())
+            .and_then(|name| name.to_str())
+            .and_then(|name| Language::from_str(name).ok())
+    };
+
+    match from_extension.or_else(from_directory) {
+        Some(language) => Ok(language),
+        None => bail!("no language found for {}", path.display()),
+    }
+}
+
+#[cfg(test)]
+#[cfg(any(feature = "elm", feature = "rust"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(feature = "rust")]
+    fn language_from_header_comment() {
+        assert_eq!(
+            language_for(
+                Path::new("query.scm"),
+                ";; some description\n; language: rust\n(identifier)"
+            )
+            .unwrap(),
+            Language::Rust
+        )
+    }
+
+    #[test]
+    #[cfg(feature = "rust")]
+    fn header_comment_has_to_come_before_the_query() {
+        assert!(language_for(Path::new("query.scm"), "(identifier)\n; language: rust").is_err())
+    }
+
+    #[test]
+    #[cfg(feature = "rust")]
+    fn language_from_extension() {
+        assert_eq!(
+            language_for(Path::new("no-unwrap.rust.scm"), "(identifier)").unwrap(),
+            Language::Rust
+        )
+    }
+
+    #[test]
+    #[cfg(feature = "elm")]
+    fn language_from_directory() {
+        assert_eq!(
+            language_for(Path::new("queries/elm/imports.scm"), "(identifier)").unwrap(),
+            Language::Elm
+        )
+    }
+}
@@ -0,0 +1,405 @@
+use anyhow::{anyhow, Context};
+use serde::ser::SerializeStruct;
+use serde::{Serialize, Serializer};
+use similar::TextDiff;
+use std::collections::BTreeMap;
+use std::fmt::{self, Display};
+use std::fs;
+use std::path::PathBuf;
+use tree_grepper::{ExtractedFile, FileError, ParseError, Severity};
+
+/// Something we print about the search, in whatever format we're printing.
+/// In line-oriented output, each record prints one or more full lines. In
+/// JSON output, each record is one object.
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum Record {
+    Matches(ExtractedFile),
+    GroupedMatches(GroupedFile),
+    Count(FileCount),
+    Total(TotalCount),
+    FileName(FileName),
+    Rewrite(Rewritten),
+    ParseErrors(FileParseErrors),
+}
+
+impl Record {
+    /// Does this record mean we found what we were looking for? We use this
+    /// to pick an exit code.
+    pub fn is_match(&self) -> bool {
+        match self {
+            Record::Matches(extracted_file) => !extracted_file.matches().is_empty(),
+            Record::GroupedMatches(grouped) => !grouped.0.matches().is_empty(),
+            Record::Count(count) => count.count > 0,
+            Record::Total(_) => false,
+            Record::FileName(_) => true,
+            Record::Rewrite(rewritten) => !rewritten.extracted_file.edits().is_empty(),
+            Record::ParseErrors(file) => !file.parse_errors.is_empty(),
+        }
+    }
+
+    /// The most severe rule this record matched, if it came from rules. We
+    /// use this to pick an exit code with `--fail-on`.
+    pub fn severity(&self) -> Option<Severity> {
+        match self {
+            Record::Matches(extracted_file) => extracted_file.highest_severity(),
+            Record::GroupedMatches(grouped) => grouped.0.highest_severity(),
+            Record::Count(count) => count.severity,
+            Record::Total(_) => None,
+            Record::FileName(file_name) => file_name.severity,
+            Record::Rewrite(rewritten) => rewritten.extracted_file.highest_severity(),
+            Record::ParseErrors(_) => None,
+        }
+    }
+}
+
+impl Display for Record {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Record::Matches(extracted_file) => write!(f, "{}", extracted_file),
+            Record::GroupedMatches(grouped) => write!(f, "{}", grouped),
+            Record::Count(count) => write!(f, "{}", count),
+            Record::Total(total) => write!(f, "{}", total),
+            Record::FileName(file_name) => write!(f, "{}", file_name),
+            Record::Rewrite(rewritten) => write!(f, "{}", rewritten),
+            Record::ParseErrors(file) => write!(f, "{}", file),
+        }
+    }
+}
+
+/// A file whose matches we print grouped by query match instead of one
+/// capture at a time (see `ExtractedFile::grouped_matches`.)
+#[derive(Debug)]
+pub struct GroupedFile(pub ExtractedFile);
+
+impl Serialize for GroupedFile {
+    fn serialize<S>(&self, sz: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut out = sz.serialize_struct("GroupedFile", 4)?;
+        out.serialize_field("file", &self.0.file())?;
+        out.serialize_field("file_type", self.0.file_type())?;
+        out.serialize_field("has_errors", &self.0.has_errors())?;
+        out.serialize_field("matches", &self.0.grouped_matches())?;
+        out.end()
+    }
+}
+
+impl Display for GroupedFile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = self.0.display_name();
+
+        for group in self.0.grouped_matches() {
+            let first = group.first();
+            let base = first.coordinates().base();
+
+            writeln!(
+                f,
+                "{}:{}:{}: match {} (pattern {})",
+                name,
+                first.start().row + base,
+                first.start().column + base,
+                first.match_id(),
+                first.pattern(),
+            )?;
+
+            for capture in group.captures() {
+                writeln!(
+                    f,
+                    "  @{} {}:{}: {}",
+                    capture.name(),
+                    capture.start().row + base,
+                    capture.start().column + base,
+                    capture.text(),
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct FileCount {
+    #[serde(skip)]
+    display_name: String,
+    file: Option<PathBuf>,
+    file_type: String,
+    count: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    captures: Option<BTreeMap<String, usize>>,
+    #[serde(skip)]
+    severity: Option<Severity>,
+}
+
+impl FileCount {
+    fn new(extracted_file: &ExtractedFile, by_capture: bool) -> FileCount {
+        FileCount {
+            display_name: extracted_file.display_name().to_string(),
+            file: extracted_file.file().map(|path| path.to_owned()),
+            file_type: extracted_file.file_type().to_string(),
+            count: extracted_file.matches().len(),
+            captures: if by_capture {
+                let mut captures = BTreeMap::new();
+                for extracted_match in extracted_file.matches() {
+                    *captures
+                        .entry(extracted_match.name().to_string())
+                        .or_insert(0) += 1;
+                }
+
+                Some(captures)
+            } else {
+                None
+            },
+            severity: extracted_file.highest_severity(),
+        }
+    }
+}
+
+impl Display for FileCount {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.captures {
+            Some(captures) => {
+                for (name, count) in captures {
+                    writeln!(f, "{}:{}:{}", self.display_name, name, count)?
+                }
+
+                Ok(())
+            }
+            None => writeln!(f, "{}:{}", self.display_name, self.count),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct TotalCount {
+    total: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    captures: Option<BTreeMap<String, usize>>,
+}
+
+impl TotalCount {
+    fn new(by_capture: bool) -> TotalCount {
+        TotalCount {
+            total: 0,
+            captures: if by_capture {
+                Some(BTreeMap::new())
+            } else {
+                None
+            },
+        }
+    }
+
+    fn add(&mut self, file_count: &FileCount) {
+        self.total += file_count.count;
+
+        if let (Some(total_captures), Some(file_captures)) =
+            (&mut self.captures, &file_count.captures)
+        {
+            for (name, count) in file_captures {
+                *total_captures.entry(name.to_string()).or_insert(0) += count;
+            }
+        }
+    }
+}
+
+impl Display for TotalCount {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.captures {
+            Some(captures) => {
+                for (name, count) in captures {
+                    writeln!(f, "total:{}:{}", name, count)?
+                }
+
+                Ok(())
+            }
+            None => writeln!(f, "total:{}", self.total),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct FileName {
+    #[serde(skip)]
+    display_name: String,
+    file: Option<PathBuf>,
+    file_type: String,
+    #[serde(skip)]
+    severity: Option<Severity>,
+}
+
+impl From<ExtractedFile> for FileName {
+    fn from(extracted_file: ExtractedFile) -> FileName {
+        FileName {
+            display_name: extracted_file.display_name().to_string(),
+            file: extracted_file.file().map(|path| path.to_owned()),
+            file_type: extracted_file.file_type().to_string(),
+            severity: extracted_file.highest_severity(),
+        }
+    }
+}
+
+impl Display for FileName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}", self.display_name)
+    }
+}
+
+/// Where a file has syntax errors, from `--report-parse-errors`.
+#[derive(Debug, Serialize)]
+pub struct FileParseErrors {
+    #[serde(skip)]
+    display_name: String,
+    file: Option<PathBuf>,
+    file_type: String,
+    parse_errors: Vec<ParseError>,
+}
+
+impl From<ExtractedFile> for FileParseErrors {
+    fn from(extracted_file: ExtractedFile) -> FileParseErrors {
+        FileParseErrors {
+            display_name: extracted_file.display_name().to_string(),
+            file: extracted_file.file().map(|path| path.to_owned()),
+            file_type: extracted_file.file_type().to_string(),
+            parse_errors: extracted_file.parse_errors().to_vec(),
+        }
+    }
+}
+
+impl Display for FileParseErrors {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for error in &self.parse_errors {
+            let base = error.coordinates().base();
+
+            writeln!(
+                f,
+                "{}:{}:{}: {}",
+                self.display_name,
+                error.start().row + base,
+                error.start().column + base,
+                error,
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A file with edits from `--rewrite`. If we applied them, we print the file
+/// name. Otherwise, we print a unified diff that `git apply` or `patch -p1`
+/// could apply later. In JSON, the edits are part of the extracted file.
+#[derive(Debug)]
+pub struct Rewritten {
+    extracted_file: ExtractedFile,
+    applied: bool,
+}
+
+impl Rewritten {
+    pub fn dry_run(extracted_file: ExtractedFile) -> Rewritten {
+        Rewritten {
+            extracted_file,
+            applied: false,
+        }
+    }
+
+    pub fn apply(extracted_file: ExtractedFile) -> Result<Rewritten, FileError> {
+        let result = match (extracted_file.file(), extracted_file.rewritten()) {
+            (Some(path), Some(rewritten)) => fs::write(path, rewritten)
+                .with_context(|| format!("could not write {}", path.display())),
+            (None, _) => Err(anyhow!("there's no file to rewrite in place")),
+            (_, None) => Err(anyhow!(
+                "the source wasn't kept for rewriting. This is an internal error and should be reported."
+            )),
+        };
+
+        match result {
+            Ok(()) => Ok(Rewritten {
+                extracted_file,
+                applied: true,
+            }),
+            Err(err) => Err(FileError::new(
+                extracted_file.file().map(|path| path.to_owned()),
+                err,
+            )),
+        }
+    }
+}
+
+impl Serialize for Rewritten {
+    fn serialize<S>(&self, sz: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        self.extracted_file.serialize(sz)
+    }
+}
+
+impl Display for Rewritten {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = self.extracted_file.display_name();
+
+        if self.applied {
+            return writeln!(f, "{}", name);
+        }
+
+        let (source, rewritten) = match (
+            self.extracted_file.source(),
+            self.extracted_file.rewritten(),
+        ) {
+            (Some(source), Some(rewritten)) => (source, rewritten),
+            _ => return Ok(()),
+        };
+
+        let source = String::from_utf8_lossy(source);
+        let rewritten = String::from_utf8_lossy(&rewritten);
+
+        write!(
+            f,
+            "{}",
+            TextDiff::from_lines(&source, &rewritten)
+                .unified_diff()
+                .header(&format!("a/{}", name), &format!("b/{}", name))
+        )
+    }
+}
+
+/// Turns extracted files into per-file counts, followed by a total once we've
+/// seen all the files.
+pub struct Counts<I> {
+    files: I,
+    by_capture: bool,
+    total: Option<TotalCount>,
+}
+
+impl<I> Counts<I> {
+    pub fn new(files: I, by_capture: bool) -> Counts<I> {
+        Counts {
+            files,
+            by_capture,
+            total: Some(TotalCount::new(by_capture)),
+        }
+    }
+}
+
+impl<I> Iterator for Counts<I>
+where
+    I: Iterator<Item = Result<ExtractedFile, FileError>>,
+{
+    type Item = Result<Record, FileError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.files.next() {
+            Some(Ok(extracted_file)) => {
+                let count = FileCount::new(&extracted_file, self.by_capture);
+                if let Some(total) = &mut self.total {
+                    total.add(&count);
+                }
+
+                Some(Ok(Record::Count(count)))
+            }
+            Some(Err(error)) => Some(Err(error)),
+            None => self.total.take().map(|total| Ok(Record::Total(total))),
+        }
+    }
+}
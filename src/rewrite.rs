@@ -0,0 +1,383 @@
+use crate::extractor::{Coordinates, SerializePoint};
+use anyhow::{bail, Result};
+use serde::ser::{SerializeStruct, Serializer};
+use serde::Serialize;
+use std::str::FromStr;
+use std::sync::Arc;
+use tree_sitter::{Point, QueryMatch};
+
+/// Replace every node captured as `capture` with `template`, filled in with
+/// the text of other captures from the same match.
+#[derive(Debug, Clone)]
+pub struct Rewrite {
+    capture: String,
+    template: Template,
+    rule: Option<String>,
+}
+
+impl Rewrite {
+    /// `capture` may be given with or without its leading `@`.
+    pub fn new(capture: &str, template: &str) -> Result<Rewrite> {
+        let capture = capture.strip_prefix('@').unwrap_or(capture);
+        if capture.is_empty() {
+            bail!("I need the name of a capture to rewrite")
+        }
+
+        Ok(Rewrite {
+            capture: capture.to_string(),
+            template: Template::from_str(template)?,
+            rule: None,
+        })
+    }
+
+    /// Only rewrite matches of the rule with this id.
+    pub(crate) fn for_rule(mut self, rule_id: &str) -> Rewrite {
+        self.rule = Some(rule_id.to_string());
+        self
+    }
+
+    pub(crate) fn rule(&self) -> Option<&str> {
+        self.rule.as_deref()
+    }
+
+    /// The name of the capture this rewrite replaces, without the `@`.
+    pub fn capture(&self) -> &str {
+        &self.capture
+    }
+
+    /// The names of the captures the template interpolates, without the `@`.
+    pub fn referenced_captures(&self) -> impl Iterator<Item = &str> {
+        self.template.parts.iter().filter_map(|part| match part {
+            TemplatePart::Literal(_) => None,
+            TemplatePart::Capture(name) => Some(name.as_str()),
+        })
+    }
+
+    /// Figure out what this rewrite would change in a single query match.
+    /// Captures the template refers to but which aren't in the match (say,
+    /// because they're optional in the query) are filled in as empty.
+    pub(crate) fn edits(
+        &self,
+        capture_names: &[Arc<str>],
+        query_match: &QueryMatch,
+        source: &[u8],
+        coordinates: Coordinates,
+    ) -> Result<Vec<Edit>> {
+        let mut edits = Vec::new();
+
+        for capture in query_match.captures {
+            if *capture_names[capture.index as usize] != *self.capture {
+                continue;
+            }
+
+            let mut replacement = String::new();
+            for part in &self.template.parts {
+                match part {
+                    TemplatePart::Literal(literal) => replacement.push_str(literal),
+                    TemplatePart::Capture(name) => {
+                        if let Some(other) = query_match
+                            .captures
+                            .iter()
+                            .find(|other| &*capture_names[other.index as usize] == name)
+                        {
+                            replacement.push_str(other.node.utf8_text(source)?)
+                        }
+                    }
+                }
+            }
+
+            let node = capture.node;
+            edits.push(Edit {
+                start: node.start_position(),
+                end: node.end_position(),
+                start_byte: node.start_byte(),
+                end_byte: node.end_byte(),
+                replacement,
+                coordinates,
+            })
+        }
+
+        Ok(edits)
+    }
+}
+
+/// A template like `@name(@args)`. `@name` or `@{name}` is replaced with the
+/// text of the capture called `name`, and `@@` is a literal `@`. Capture
+/// names with dots in them (like `@function.name`) need the braces, since
+/// otherwise we couldn't tell `@obj.len()` from a capture called `obj.len`.
+#[derive(Debug, Clone)]
+struct Template {
+    parts: Vec<TemplatePart>,
+}
+
+#[derive(Debug, Clone)]
+enum TemplatePart {
+    Literal(String),
+    Capture(String),
+}
+
+impl FromStr for Template {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = s.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            if c != '@' {
+                literal.push(c);
+                continue;
+            }
+
+            let name = match chars.peek() {
+                Some('@') => {
+                    chars.next();
+                    literal.push('@');
+                    continue;
+                }
+                Some('{') => {
+                    chars.next();
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => bail!("unclosed `@{{` in template `{}`", s),
+                        }
+                    }
+                    name
+                }
+                _ => {
+                    let mut name = String::new();
+                    while let Some(c) = chars.next_if(|c| is_capture_name_char(*c)) {
+                        name.push(c)
+                    }
+                    name
+                }
+            };
+
+            if name.is_empty() {
+                bail!(
+                    "expected a capture name after `@` in template `{}` (use `@@` for a literal `@`)",
+                    s
+                )
+            }
+
+            if !literal.is_empty() {
+                parts.push(TemplatePart::Literal(std::mem::take(&mut literal)));
+            }
+            parts.push(TemplatePart::Capture(name));
+        }
+
+        if !literal.is_empty() {
+            parts.push(TemplatePart::Literal(literal));
+        }
+
+        Ok(Template { parts })
+    }
+}
+
+/// Tree-sitter allows a few more characters than identifiers do in capture
+/// names. Dots are allowed too, but only inside `@{...}`.
+fn is_capture_name_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '-'
+}
+
+/// A single replacement of a range of a file.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Edit {
+    start_byte: usize,
+    end_byte: usize,
+    start: Point,
+    end: Point,
+    replacement: String,
+    coordinates: Coordinates,
+}
+
+impl Edit {
+    pub fn start(&self) -> Point {
+        self.start
+    }
+
+    pub fn end(&self) -> Point {
+        self.end
+    }
+
+    pub fn start_byte(&self) -> usize {
+        self.start_byte
+    }
+
+    pub fn end_byte(&self) -> usize {
+        self.end_byte
+    }
+
+    pub fn replacement(&self) -> &str {
+        &self.replacement
+    }
+
+    /// Sort edits so they can be applied from the start of the file to the
+    /// end, dropping exact duplicates (which happen when several patterns
+    /// match the same node.) Two different edits touching the same bytes
+    /// would make the result depend on which we applied first, so we refuse
+    /// to guess.
+    pub(crate) fn check(mut edits: Vec<Edit>) -> Result<Vec<Edit>> {
+        edits.sort();
+        edits.dedup();
+
+        for pair in edits.windows(2) {
+            let (before, after) = (&pair[0], &pair[1]);
+
+            if after.start_byte < before.end_byte
+                || (after.start_byte == before.start_byte && before.start_byte == before.end_byte)
+            {
+                let base = before.coordinates.base();
+                bail!(
+                    "overlapping rewrites at {}:{}-{}:{} and {}:{}-{}:{}",
+                    before.start.row + base,
+                    before.start.column + base,
+                    before.end.row + base,
+                    before.end.column + base,
+                    after.start.row + base,
+                    after.start.column + base,
+                    after.end.row + base,
+                    after.end.column + base,
+                )
+            }
+        }
+
+        Ok(edits)
+    }
+
+    /// Apply edits (sorted and checked, as in `ExtractedFile::edits`) to the
+    /// source they were made from.
+    pub(crate) fn apply(source: &[u8], edits: &[Edit]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(source.len());
+        let mut copied_up_to = 0;
+
+        for edit in edits {
+            out.extend_from_slice(&source[copied_up_to..edit.start_byte]);
+            out.extend_from_slice(edit.replacement.as_bytes());
+            copied_up_to = edit.end_byte;
+        }
+
+        out.extend_from_slice(&source[copied_up_to..]);
+        out
+    }
+}
+
+impl Serialize for Edit {
+    fn serialize<S>(&self, sz: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut out = sz.serialize_struct("Edit", 5)?;
+        out.serialize_field("start", &SerializePoint(self.start, self.coordinates))?;
+        out.serialize_field("end", &SerializePoint(self.end, self.coordinates))?;
+        out.serialize_field("start_byte", &self.start_byte)?;
+        out.serialize_field("end_byte", &self.end_byte)?;
+        out.serialize_field("replacement", &self.replacement)?;
+        out.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "javascript")]
+    use crate::{extractor::Extractor, language::Language};
+    #[cfg(feature = "javascript")]
+    use tree_sitter::Parser;
+
+    #[cfg(feature = "javascript")]
+    fn rewrite(query: &str, rewrite: Rewrite, source: &str) -> Result<String> {
+        let lang = Language::JavaScript;
+        let query = lang.parse_query(query).unwrap();
+        let extractor = Extractor::new(lang, query)
+            .keep_source(true)
+            .rewrite(rewrite);
+
+        let extracted = extractor
+            .extract_from_text(None, source.as_bytes(), &mut Parser::new())?
+            .unwrap();
+
+        Ok(String::from_utf8(extracted.rewritten().unwrap()).unwrap())
+    }
+
+    #[test]
+    #[cfg(feature = "javascript")]
+    fn test_replaces_captures() {
+        assert_eq!(
+            rewrite(
+                "(call_expression function: (identifier)@fn (#eq? @fn foo))",
+                Rewrite::new("@fn", "bar").unwrap(),
+                "foo(1); baz(2); foo(3)",
+            )
+            .unwrap(),
+            "bar(1); baz(2); bar(3)",
+        )
+    }
+
+    #[test]
+    #[cfg(feature = "javascript")]
+    fn test_interpolates_other_captures() {
+        assert_eq!(
+            rewrite(
+                "(call_expression function: (identifier)@_fn arguments: (arguments (_)@arg))@call",
+                Rewrite::new("call", "@{_fn}Checked(@arg, @@here)").unwrap(),
+                "foo(1)",
+            )
+            .unwrap(),
+            "fooChecked(1, @here)",
+        )
+    }
+
+    #[test]
+    #[cfg(feature = "javascript")]
+    fn test_bare_capture_names_stop_at_dots() {
+        assert_eq!(
+            rewrite(
+                "(expression_statement (identifier)@x)",
+                Rewrite::new("x", "@x.len()").unwrap(),
+                "foo;",
+            )
+            .unwrap(),
+            "foo.len();",
+        )
+    }
+
+    #[test]
+    #[cfg(feature = "javascript")]
+    fn test_dotted_capture_names_need_braces() {
+        assert_eq!(
+            rewrite(
+                "(expression_statement (identifier)@x.y)",
+                Rewrite::new("x.y", "@{x.y}.len()").unwrap(),
+                "foo;",
+            )
+            .unwrap(),
+            "foo.len();",
+        )
+    }
+
+    #[test]
+    #[cfg(feature = "javascript")]
+    fn test_overlapping_edits_are_an_error() {
+        let result = rewrite(
+            "(call_expression)@call",
+            Rewrite::new("call", "x").unwrap(),
+            "foo(bar(1))",
+        );
+
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .starts_with("overlapping rewrites"));
+    }
+
+    #[test]
+    fn test_templates_need_capture_names() {
+        assert!(Rewrite::new("fn", "foo@").is_err());
+        assert!(Rewrite::new("fn", "foo@{bar").is_err());
+    }
+}
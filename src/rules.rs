@@ -0,0 +1,204 @@
+use crate::language::Language;
+use crate::rewrite::Rewrite;
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::fmt::{self, Display};
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+/// A named query with a message to show for each match, like a lint rule.
+/// Rules are loaded from rule packs: TOML, YAML, or JSON files with a list of
+/// rules under `rules`.
+#[derive(Debug, Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    id: String,
+    #[serde(deserialize_with = "deserialize_language")]
+    language: Language,
+    query: String,
+    message: String,
+    #[serde(default)]
+    severity: Severity,
+    fix: Option<Fix>,
+}
+
+impl Rule {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn language(&self) -> &Language {
+        &self.language
+    }
+
+    pub fn query(&self) -> &str {
+        &self.query
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn severity(&self) -> Severity {
+        self.severity
+    }
+
+    /// The rewrite that fixes a match of this rule, if the rule has one. It
+    /// only applies to matches of this rule, even if other rules use the
+    /// same capture names.
+    pub fn fix(&self) -> Result<Option<Rewrite>> {
+        match &self.fix {
+            Some(fix) => Ok(Some(
+                Rewrite::new(&fix.capture, &fix.template)
+                    .with_context(|| format!("could not parse the fix for {}", self.id))?
+                    .for_rule(&self.id),
+            )),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Replace `capture` with `template` (see `Rewrite`) to fix a match.
+#[derive(Debug, Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(deny_unknown_fields)]
+struct Fix {
+    capture: String,
+    template: String,
+}
+
+/// How bad a match of a rule is. Rules without a severity are warnings.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+impl Default for Severity {
+    fn default() -> Self {
+        Severity::Warning
+    }
+}
+
+impl FromStr for Severity {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "info" => Ok(Severity::Info),
+            "warning" => Ok(Severity::Warning),
+            "error" => Ok(Severity::Error),
+            _ => bail!("unknown severity {}. Try one of: info, warning, error", s),
+        }
+    }
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Info => write!(f, "info"),
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+fn deserialize_language<'de, D>(deserializer: D) -> Result<Language, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let name = String::deserialize(deserializer)?;
+    Language::from_str(&name).map_err(serde::de::Error::custom)
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RulePack {
+    rules: Vec<Rule>,
+}
+
+/// Load the rules from a rule pack, choosing a format from the extension.
+pub(crate) fn load(path: &Path) -> Result<Vec<Rule>> {
+    let source = fs::read_to_string(path)
+        .with_context(|| format!("could not read rules from {}", path.display()))?;
+
+    let pack: RulePack = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("toml") => toml::from_str(&source).map_err(anyhow::Error::from),
+        Some("yaml") | Some("yml") => serde_yaml::from_str(&source).map_err(anyhow::Error::from),
+        Some("json") => serde_json::from_str(&source).map_err(anyhow::Error::from),
+        _ => bail!(
+            "I don't know how to read rules from {}. Use a .toml, .yaml, or .json file.",
+            path.display()
+        ),
+    }
+    .with_context(|| format!("could not parse rules from {}", path.display()))?;
+
+    Ok(pack.rules)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(feature = "rust")]
+    fn rules_from_toml() {
+        let pack: RulePack = toml::from_str(
+            r#"
+            [[rules]]
+            id = "no-unwrap"
+            language = "rust"
+            query = "(call_expression)@call"
+            message = "don't unwrap"
+            severity = "error"
+
+            [rules.fix]
+            capture = "call"
+            template = "@call?"
+
+            [[rules]]
+            id = "todo"
+            language = "rust"
+            query = "(line_comment)@comment"
+            message = "finish this"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(pack.rules.len(), 2);
+        assert_eq!(pack.rules[0].id(), "no-unwrap");
+        assert_eq!(pack.rules[0].language(), &Language::Rust);
+        assert_eq!(pack.rules[0].severity(), Severity::Error);
+        assert!(pack.rules[0].fix().unwrap().is_some());
+        assert_eq!(pack.rules[1].severity(), Severity::Warning);
+        assert!(pack.rules[1].fix().unwrap().is_none());
+    }
+
+    #[test]
+    #[cfg(feature = "rust")]
+    fn rules_from_yaml() {
+        let pack: RulePack = serde_yaml::from_str(
+            "rules:\n- id: todo\n  language: rust\n  query: (line_comment)@comment\n  message: finish this\n  severity: info\n",
+        )
+        .unwrap();
+
+        assert_eq!(pack.rules[0].severity(), Severity::Info);
+    }
+
+    #[test]
+    fn unknown_languages_are_an_error() {
+        let result: Result<RulePack, _> = serde_json::from_str(
+            r#"{"rules": [{"id": "x", "language": "cobol", "query": "(x)", "message": "x"}]}"#,
+        );
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn severities_are_ordered() {
+        assert!(Severity::Info < Severity::Warning);
+        assert!(Severity::Warning < Severity::Error);
+    }
+}
@@ -0,0 +1,320 @@
+use anyhow::{Context, Result};
+use clap::crate_version;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::Path;
+use tree_grepper::{ExtractedFile, ExtractedMatch, FileError, Severity};
+
+/// Collects matches into a SARIF 2.1.0 log, for code scanning tools. SARIF
+/// is one big document (the rules we used have to be listed along with the
+/// results) so unlike our other formats, we can't print anything until the
+/// search is done.
+///
+/// Each capture becomes one result. Its rule is the rule the match came from,
+/// if any, then the query's label, then the capture name. SARIF always counts
+/// lines and columns from 1 and measures columns in UTF-16 code units, no
+/// matter what `--coordinates` says.
+#[derive(Default)]
+pub struct SarifLog {
+    rules: BTreeMap<String, ReportingDescriptor>,
+    results: Vec<SarifResult>,
+    notifications: Vec<Notification>,
+}
+
+impl SarifLog {
+    pub fn add_file(&mut self, extracted_file: &ExtractedFile) {
+        let uri = uri_for(extracted_file.display_name());
+
+        for extracted_match in extracted_file.matches() {
+            let rule_id = self.rule_for(extracted_match);
+
+            self.results.push(SarifResult {
+                level: extracted_match.rule().map(|rule| level(rule.severity())),
+                message: Message {
+                    text: match extracted_match.rule() {
+                        Some(rule) => rule.message().to_string(),
+                        None => format!(
+                            "@{} matched {}",
+                            extracted_match.name(),
+                            extracted_match.kind()
+                        ),
+                    },
+                },
+                locations: vec![Location {
+                    physical_location: PhysicalLocation {
+                        artifact_location: ArtifactLocation { uri: uri.clone() },
+                        region: Some(region(extracted_match, extracted_file.source())),
+                    },
+                }],
+                rule_id,
+            })
+        }
+    }
+
+    pub fn add_error(&mut self, error: &FileError) {
+        self.notifications.push(Notification {
+            level: if error.skipped().is_some() {
+                "note"
+            } else {
+                "error"
+            },
+            message: Message {
+                text: format!("{:#}", error.error()),
+            },
+            locations: error
+                .path()
+                .map(|path| {
+                    vec![Location {
+                        physical_location: PhysicalLocation {
+                            artifact_location: ArtifactLocation {
+                                uri: uri_for(&path.display().to_string()),
+                            },
+                            region: None,
+                        },
+                    }]
+                })
+                .unwrap_or_default(),
+        })
+    }
+
+    /// Find (or make) the rule a match should be reported under.
+    fn rule_for(&mut self, extracted_match: &ExtractedMatch) -> String {
+        let descriptor = match extracted_match.rule() {
+            Some(rule) => ReportingDescriptor {
+                id: rule.id().to_string(),
+                short_description: Some(Message {
+                    text: rule.message().to_string(),
+                }),
+                default_configuration: Some(Configuration {
+                    level: level(rule.severity()),
+                }),
+            },
+            None => ReportingDescriptor {
+                id: match extracted_match.query_label() {
+                    Some(label) => label.to_string(),
+                    None => extracted_match.name().to_string(),
+                },
+                short_description: None,
+                default_configuration: None,
+            },
+        };
+
+        let id = descriptor.id.clone();
+        self.rules.entry(id.clone()).or_insert(descriptor);
+        id
+    }
+
+    pub fn write(self, mut out: impl Write) -> Result<()> {
+        let log = Log {
+            schema: "https://json.schemastore.org/sarif-2.1.0.json",
+            version: "2.1.0",
+            runs: vec![Run {
+                tool: Tool {
+                    driver: Driver {
+                        name: "tree-grepper",
+                        version: crate_version!(),
+                        information_uri: "https://github.com/BrianHicks/tree-grepper",
+                        rules: self.rules.into_values().collect(),
+                    },
+                },
+                invocations: vec![Invocation {
+                    execution_successful: self
+                        .notifications
+                        .iter()
+                        .all(|notification| notification.level != "error"),
+                    tool_execution_notifications: self.notifications,
+                }],
+                results: self.results,
+            }],
+        };
+
+        serde_json::to_writer_pretty(&mut out, &log).context("could not write SARIF output")?;
+        writeln!(out).context("could not write SARIF output")
+    }
+}
+
+fn level(severity: Severity) -> &'static str {
+    match severity {
+        Severity::Error => "error",
+        Severity::Warning => "warning",
+        Severity::Info => "note",
+    }
+}
+
+/// SARIF wants URIs. Relative paths stay relative (so code scanning tools can
+/// resolve them against the repo) and absolute ones become `file://` URIs.
+fn uri_for(path: &str) -> String {
+    let path = path.strip_prefix("./").unwrap_or(path).replace('\\', "/");
+
+    let mut uri = String::with_capacity(path.len());
+    if Path::new(&path).is_absolute() {
+        uri.push_str("file://");
+        if !path.starts_with('/') {
+            // Windows paths like `C:/src` need a slash before the drive.
+            uri.push('/');
+        }
+    }
+
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' | b':' => {
+                uri.push(byte as char)
+            }
+            _ => uri.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+
+    uri
+}
+
+fn region(extracted_match: &ExtractedMatch, source: Option<&[u8]>) -> Region {
+    let start = extracted_match.start();
+    let end = extracted_match.end();
+
+    Region {
+        start_line: start.row + 1,
+        start_column: utf16_column(source, extracted_match.start_byte(), start.column) + 1,
+        end_line: end.row + 1,
+        end_column: utf16_column(source, extracted_match.end_byte(), end.column) + 1,
+        byte_offset: extracted_match.start_byte(),
+        byte_length: extracted_match.end_byte() - extracted_match.start_byte(),
+    }
+}
+
+/// Tree-sitter columns count bytes, but SARIF columns count UTF-16 code
+/// units. They're the same for ASCII, so without the source we fall back to
+/// the byte column.
+fn utf16_column(source: Option<&[u8]>, byte: usize, column: usize) -> usize {
+    match source.and_then(|source| source.get(byte - column..byte)) {
+        Some(line) => String::from_utf8_lossy(line).encode_utf16().count(),
+        None => column,
+    }
+}
+
+#[derive(Serialize)]
+struct Log {
+    #[serde(rename = "$schema")]
+    schema: &'static str,
+    version: &'static str,
+    runs: Vec<Run>,
+}
+
+#[derive(Serialize)]
+struct Run {
+    tool: Tool,
+    invocations: Vec<Invocation>,
+    results: Vec<SarifResult>,
+}
+
+#[derive(Serialize)]
+struct Tool {
+    driver: Driver,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Driver {
+    name: &'static str,
+    version: &'static str,
+    information_uri: &'static str,
+    rules: Vec<ReportingDescriptor>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ReportingDescriptor {
+    id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    short_description: Option<Message>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    default_configuration: Option<Configuration>,
+}
+
+#[derive(Serialize)]
+struct Configuration {
+    level: &'static str,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Invocation {
+    execution_successful: bool,
+    tool_execution_notifications: Vec<Notification>,
+}
+
+#[derive(Serialize)]
+struct Notification {
+    level: &'static str,
+    message: Message,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    locations: Vec<Location>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SarifResult {
+    rule_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    level: Option<&'static str>,
+    message: Message,
+    locations: Vec<Location>,
+}
+
+#[derive(Serialize)]
+struct Message {
+    text: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Location {
+    physical_location: PhysicalLocation,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PhysicalLocation {
+    artifact_location: ArtifactLocation,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    region: Option<Region>,
+}
+
+#[derive(Serialize)]
+struct ArtifactLocation {
+    uri: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Region {
+    start_line: usize,
+    start_column: usize,
+    end_line: usize,
+    end_column: usize,
+    byte_offset: usize,
+    byte_length: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn relative_paths_stay_relative() {
+        assert_eq!(uri_for("./src/main.rs"), "src/main.rs");
+    }
+
+    #[test]
+    fn absolute_paths_become_file_uris() {
+        assert_eq!(uri_for("/tmp/a b.rs"), "file:///tmp/a%20b.rs");
+    }
+
+    #[test]
+    fn columns_count_utf16_code_units() {
+        let source = "let é = 1;".as_bytes();
+        // `=` is at byte 9 but code unit 8, since `é` is two bytes in UTF-8
+        assert_eq!(utf16_column(Some(source), 9, 9), 8);
+        assert_eq!(utf16_column(None, 9, 9), 9);
+    }
+}
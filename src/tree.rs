@@ -0,0 +1,314 @@
+use anyhow::{bail, Context, Error, Result};
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use tree_grepper::{Coordinates, Language};
+use tree_sitter::{Node, Parser, Point};
+
+/// How much of a capture's text we show after a leaf node in the indented
+/// tree. Long string literals and comments would drown out the structure.
+const MAX_TEXT_CHARS: usize = 40;
+
+#[derive(Debug)]
+pub struct ShowTreeOpts {
+    pub language: Language,
+    /// The file to parse, or `-` for stdin.
+    pub path: PathBuf,
+    pub format: TreeFormat,
+    pub lines: Option<LineRange>,
+    pub coordinates: Coordinates,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum TreeFormat {
+    /// One named node per line, indented by depth, with field names,
+    /// positions, and the text of leaf nodes.
+    Indented,
+    /// The tree as a single S-expression, the way tree-sitter prints it.
+    /// This is the same syntax queries use, so it's a good starting point
+    /// for writing one.
+    Sexp,
+}
+
+/// Only show nodes that touch these lines (inclusive, counted the same way
+/// as the rest of the output.)
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct LineRange {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl FromStr for LineRange {
+    type Err = Error;
+
+    /// `10:20`, or just `10` for a single line.
+    fn from_str(s: &str) -> Result<Self> {
+        let (start, end) = s.split_once(':').unwrap_or((s, s));
+
+        let range = LineRange {
+            start: usize::from_str(start.trim())
+                .with_context(|| format!("could not parse a line number from {}", start))?,
+            end: usize::from_str(end.trim())
+                .with_context(|| format!("could not parse a line number from {}", end))?,
+        };
+
+        if range.start > range.end {
+            bail!("the line range {} ends before it starts", s)
+        }
+
+        Ok(range)
+    }
+}
+
+pub fn show_tree(opts: &ShowTreeOpts, out: impl Write) -> Result<()> {
+    let source = if opts.path == Path::new("-") {
+        let mut source = Vec::new();
+        io::stdin()
+            .read_to_end(&mut source)
+            .context("could not read stdin")?;
+        source
+    } else {
+        fs::read(&opts.path).with_context(|| format!("could not read {}", opts.path.display()))?
+    };
+
+    write_tree(opts, &source, out)
+}
+
+fn write_tree(opts: &ShowTreeOpts, source: &[u8], mut out: impl Write) -> Result<()> {
+    let mut parser = Parser::new();
+    parser
+        .set_language(opts.language.language())
+        .context("could not set language")?;
+    let tree = parser
+        .parse(source, None)
+        .context("could not parse to a tree. This is an internal error and should be reported.")?;
+
+    let printer = TreePrinter {
+        opts,
+        source,
+        rows: opts.lines.map(|lines| {
+            let base = opts.coordinates.base();
+            (
+                lines.start.saturating_sub(base),
+                lines.end.saturating_sub(base),
+            )
+        }),
+    };
+
+    let root = tree.root_node();
+    if printer.in_rows(root) {
+        printer
+            .write_node(&mut out, root, None, 0)
+            .context("could not print the tree")?;
+        if opts.format == TreeFormat::Sexp {
+            writeln!(out).context("could not print the tree")?;
+        }
+    }
+
+    Ok(())
+}
+
+struct TreePrinter<'a> {
+    opts: &'a ShowTreeOpts,
+    source: &'a [u8],
+    /// The line range, as 0-based rows.
+    rows: Option<(usize, usize)>,
+}
+
+impl<'a> TreePrinter<'a> {
+    /// Anonymous nodes (like punctuation and keywords) would make the tree
+    /// several times longer, so we leave them out like tree-sitter does.
+    /// Missing ones are the exception, since they're usually why a query
+    /// isn't matching.
+    fn shows(&self, node: Node) -> bool {
+        node.is_named() || node.is_missing()
+    }
+
+    fn in_rows(&self, node: Node) -> bool {
+        match self.rows {
+            Some((start, end)) => {
+                node.start_position().row <= end && node.end_position().row >= start
+            }
+            None => true,
+        }
+    }
+
+    fn write_node(
+        &self,
+        out: &mut impl Write,
+        node: Node,
+        field: Option<&str>,
+        depth: usize,
+    ) -> io::Result<()> {
+        let field = field.map(|name| format!("{}: ", name)).unwrap_or_default();
+        let kind = if node.is_missing() {
+            format!("MISSING {}", self.kind(node))
+        } else {
+            self.kind(node)
+        };
+
+        match self.opts.format {
+            TreeFormat::Indented => {
+                write!(
+                    out,
+                    "{:indent$}{}{} {}-{}",
+                    "",
+                    field,
+                    kind,
+                    self.point(node.start_position()),
+                    self.point(node.end_position()),
+                    indent = depth * 2,
+                )?;
+
+                if node.named_child_count() == 0 && !node.is_missing() {
+                    write!(out, " {}", self.text(node))?;
+                }
+
+                writeln!(out)?;
+            }
+            TreeFormat::Sexp => write!(out, "{}({}", field, kind)?,
+        }
+
+        self.write_children(out, node, None, depth + 1)?;
+
+        if self.opts.format == TreeFormat::Sexp {
+            write!(out, ")")?;
+        }
+
+        Ok(())
+    }
+
+    /// Children of nodes we don't show still get printed, in their parent's
+    /// place and with its field name if they don't have their own, the same
+    /// as tree-sitter does.
+    fn write_children(
+        &self,
+        out: &mut impl Write,
+        node: Node,
+        field: Option<&str>,
+        depth: usize,
+    ) -> io::Result<()> {
+        let mut cursor = node.walk();
+        if cursor.goto_first_child() {
+            loop {
+                let child = cursor.node();
+                let child_field = cursor.field_name().or(field);
+
+                if self.in_rows(child) {
+                    if self.shows(child) {
+                        if self.opts.format == TreeFormat::Sexp {
+                            write!(out, " ")?;
+                        }
+                        self.write_node(out, child, child_field, depth)?;
+                    } else {
+                        self.write_children(out, child, child_field, depth)?;
+                    }
+                }
+
+                if !cursor.goto_next_sibling() {
+                    break;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Anonymous node kinds are the literal text they match, so we quote
+    /// them to tell `"if"` the keyword from `if` a named node.
+    fn kind(&self, node: Node) -> String {
+        if node.is_named() {
+            node.kind().to_string()
+        } else {
+            format!("{:?}", node.kind())
+        }
+    }
+
+    fn point(&self, point: Point) -> String {
+        let base = self.opts.coordinates.base();
+        format!("{}:{}", point.row + base, point.column + base)
+    }
+
+    fn text(&self, node: Node) -> String {
+        let text = String::from_utf8_lossy(&self.source[node.byte_range()]);
+
+        if text.chars().count() > MAX_TEXT_CHARS {
+            let truncated: String = text.chars().take(MAX_TEXT_CHARS).collect();
+            format!("{:?}...", truncated)
+        } else {
+            format!("{:?}", text)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_ranges() {
+        assert_eq!(
+            LineRange::from_str("3:5").unwrap(),
+            LineRange { start: 3, end: 5 }
+        );
+        assert_eq!(
+            LineRange::from_str("3").unwrap(),
+            LineRange { start: 3, end: 3 }
+        );
+        assert!(LineRange::from_str("5:3").is_err());
+        assert!(LineRange::from_str("x").is_err());
+    }
+
+    #[cfg(feature = "rust")]
+    fn tree(format: TreeFormat, lines: Option<LineRange>, source: &str) -> String {
+        let opts = ShowTreeOpts {
+            language: Language::Rust,
+            path: PathBuf::from("-"),
+            format,
+            lines,
+            coordinates: Coordinates::OneBased,
+        };
+
+        let mut out = Vec::new();
+        write_tree(&opts, source.as_bytes(), &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    #[cfg(feature = "rust")]
+    fn indented_trees_have_fields_and_positions() {
+        assert_eq!(
+            tree(TreeFormat::Indented, None, "fn main() {}\n"),
+            concat!(
+                "source_file 1:1-2:1\n",
+                "  function_item 1:1-1:13\n",
+                "    name: identifier 1:4-1:8 \"main\"\n",
+                "    parameters: parameters 1:8-1:10 \"()\"\n",
+                "    body: block 1:11-1:13 \"{}\"\n",
+            )
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "rust")]
+    fn sexps_match_tree_sitter() {
+        assert_eq!(
+            tree(TreeFormat::Sexp, None, "fn main() {}\n"),
+            "(source_file (function_item name: (identifier) parameters: (parameters) body: (block)))\n"
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "rust")]
+    fn line_ranges_limit_the_tree() {
+        assert_eq!(
+            tree(
+                TreeFormat::Sexp,
+                Some(LineRange { start: 2, end: 2 }),
+                "fn a() {}\nfn b() {}\nfn c() {}\n"
+            ),
+            "(source_file (function_item name: (identifier) parameters: (parameters) body: (block)))\n"
+        );
+    }
+}
@@ -0,0 +1,28 @@
+#!/usr/bin/env bash
+set -euo pipefail
+
+# Build every example, or just the ones named on the command line.
+OUT_DIR="${OUT_DIR:-target}"
+targets=("$@")
+
+build() {
+  local name="$1"
+  echo "building ${name}"
+  mkdir -p "$OUT_DIR/$name"
+  cc -o "$OUT_DIR/$name/main" "examples/$name.c" 2>&1 | tee -a build.log
+}
+
+if [[ ${#targets[@]} -eq 0 ]]; then
+  for file in examples/*.c; do
+    targets+=("$(basename "$file" .c)")
+  done
+fi
+
+for target in "${targets[@]}"; do
+  case "$target" in
+    skip-*) echo "skipping $target" ;;
+    *) build "$target" ;;
+  esac
+done
+
+echo "built $(ls "$OUT_DIR" | wc -l) examples" > /dev/stderr
@@ -0,0 +1,40 @@
+#include <stdlib.h>
+#include "queue.h"
+
+#define INITIAL_CAPACITY 8
+
+struct queue {
+    int *items;
+    size_t head;
+    size_t len;
+    size_t capacity;
+};
+
+struct queue *queue_new(void) {
+    struct queue *q = malloc(sizeof(struct queue));
+    if (q == NULL) {
+        return NULL;
+    }
+
+    q->items = calloc(INITIAL_CAPACITY, sizeof(int));
+    q->head = 0;
+    q->len = 0;
+    q->capacity = INITIAL_CAPACITY;
+    return q;
+}
+
+int queue_pop(struct queue *q, int *out) {
+    if (q->len == 0) {
+        return -1;
+    }
+
+    *out = q->items[q->head];
+    q->head = (q->head + 1) % q->capacity;
+    q->len--;
+    return 0;
+}
+
+void queue_free(struct queue *q) {
+    free(q->items);
+    free(q);
+}
@@ -0,0 +1,11 @@
+#ifndef QUEUE_H
+#define QUEUE_H
+
+struct queue;
+
+struct queue *queue_new(void);
+int queue_push(struct queue *q, int item);
+int queue_pop(struct queue *q, int *out);
+void queue_free(struct queue *q);
+
+#endif
@@ -0,0 +1,30 @@
+using System;
+using System.Collections.Generic;
+using System.Linq;
+
+namespace Shop
+{
+    public record Item(string Name, decimal Price);
+
+    public class Inventory
+    {
+        private readonly Dictionary<string, int> counts = new();
+
+        public int this[string name] => counts.TryGetValue(name, out var count) ? count : 0;
+
+        public void Add(Item item, int count = 1)
+        {
+            if (count <= 0)
+            {
+                throw new ArgumentOutOfRangeException(nameof(count));
+            }
+
+            counts[item.Name] = this[item.Name] + count;
+        }
+
+        public IEnumerable<string> InStock() =>
+            counts.Where(pair => pair.Value > 0).Select(pair => pair.Key);
+
+        public override string ToString() => $"Inventory of {counts.Count} items";
+    }
+}
@@ -0,0 +1,30 @@
+package main
+
+import (
+	"encoding/json"
+	"fmt"
+	"log"
+	"net/http"
+)
+
+type Greeting struct {
+	Name    string `json:"name"`
+	Message string `json:"message"`
+}
+
+func greet(w http.ResponseWriter, r *http.Request) {
+	name := r.URL.Query().Get("name")
+	if name == "" {
+		name = "world"
+	}
+
+	greeting := Greeting{Name: name, Message: fmt.Sprintf("hello, %s", name)}
+	if err := json.NewEncoder(w).Encode(greeting); err != nil {
+		http.Error(w, err.Error(), http.StatusInternalServerError)
+	}
+}
+
+func main() {
+	http.HandleFunc("/greet", greet)
+	log.Fatal(http.ListenAndServe(":8080", nil))
+}
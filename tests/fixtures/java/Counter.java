@@ -0,0 +1,33 @@
+package example;
+
+import java.util.HashMap;
+import java.util.List;
+import java.util.Map;
+
+/** Counts how often each word shows up. */
+public class Counter<T extends Comparable<T>> {
+    private final Map<T, Integer> counts = new HashMap<>();
+
+    public void add(T item) {
+        counts.merge(item, 1, Integer::sum);
+    }
+
+    public int get(T item) {
+        return counts.getOrDefault(item, 0);
+    }
+
+    @Override
+    public String toString() {
+        StringBuilder out = new StringBuilder();
+        for (Map.Entry<T, Integer> entry : counts.entrySet()) {
+            out.append(entry.getKey()).append(": ").append(entry.getValue()).append('\n');
+        }
+        return out.toString();
+    }
+
+    public static void main(String[] args) {
+        Counter<String> counter = new Counter<>();
+        List.of(args).forEach(counter::add);
+        System.out.println(counter);
+    }
+}
@@ -0,0 +1,27 @@
+"""Keep track of what's in stock."""
+
+from dataclasses import dataclass, field
+from typing import Dict, Iterator
+
+
+@dataclass
+class Inventory:
+    counts: Dict[str, int] = field(default_factory=dict)
+
+    def add(self, name: str, count: int = 1) -> None:
+        if count <= 0:
+            raise ValueError(f"can't add {count} of {name}")
+        self.counts[name] = self.counts.get(name, 0) + count
+
+    def in_stock(self) -> Iterator[str]:
+        return (name for name, count in self.counts.items() if count > 0)
+
+    def __len__(self):
+        return sum(self.counts.values())
+
+
+if __name__ == "__main__":
+    inventory = Inventory()
+    for name in ["apple", "pear", "apple"]:
+        inventory.add(name)
+    print(sorted(inventory.in_stock()), len(inventory))
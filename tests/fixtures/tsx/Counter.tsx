@@ -0,0 +1,22 @@
+import React, { useState } from "react";
+
+interface CounterProps {
+  label: string;
+  initial?: number;
+}
+
+export function Counter({ label, initial = 0 }: CounterProps) {
+  const [count, setCount] = useState<number>(initial);
+
+  return (
+    <div className="counter">
+      <span>{label}: {count}</span>
+      <button onClick={() => setCount(count + 1)}>+</button>
+      {count > 10 && <strong>That's a lot!</strong>}
+    </div>
+  );
+}
+
+export default function App() {
+  return <Counter label="Clicks" />;
+}